@@ -7,7 +7,7 @@ use crate::{
         feature::Feature,
         privileged,
         profile::Profile,
-        store::{Object, SYSTEM_STORE, USER_STORE},
+        store::{Object, SYSTEM_STORE, USER_STORE, history},
     },
 };
 use anyhow::anyhow;
@@ -28,6 +28,10 @@ pub struct Args {
     /// Target the system set rather than the user set.
     #[arg(short, long)]
     pub system: bool,
+
+    /// A message to record alongside the change in the history.
+    #[arg(short, long)]
+    pub message: Option<String>,
 }
 impl cli::Run for Args {
     fn run(self) -> anyhow::Result<()> {
@@ -55,16 +59,13 @@ impl cli::Run for Args {
         } else if let Ok(str) = user {
             (str, false)
         } else if let Ok(str) = system {
-            USER_STORE.borrow().store(&self.name, table, &str)?;
-            (str, true)
+            // The copy is only committed to the user store if modified, so that
+            // the history doesn't record an unchanged copy.
+            (str, false)
         } else {
             (
-                {
-                    let str = fs::read_to_string(AT_CONFIG.join(kind).with_extension("toml"))?;
-                    USER_STORE.borrow().store(&self.name, table, &str)?;
-                    str
-                },
-                true,
+                fs::read_to_string(AT_CONFIG.join(kind).with_extension("toml"))?,
+                false,
             )
         };
 
@@ -75,7 +76,12 @@ impl cli::Run for Args {
         };
 
         if let Some(out) = commit {
-            USER_STORE.borrow().store(&self.name, table, &out)?;
+            let store = if self.system {
+                SYSTEM_STORE.borrow()
+            } else {
+                USER_STORE.borrow()
+            };
+            history::store(store, &self.name, table, &out, self.message)?;
             if self.name == "default" || self.feature {
                 eprintln!(
                     "{}",
@@ -84,7 +90,7 @@ impl cli::Run for Args {
                 );
             }
         } else if new {
-            USER_STORE.borrow().remove(&self.name, table)?;
+            SYSTEM_STORE.borrow().remove(&self.name, table)?;
         }
 
         Ok(())
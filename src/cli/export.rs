@@ -1,18 +1,41 @@
 //! Export user-profiles
 
-use crate::shared::{
-    env::{AT_HOME, PWD},
-    store::{Object, SYSTEM_STORE, USER_STORE},
+use crate::{
+    cli::run,
+    setup,
+    shared::{
+        Set,
+        env::{AT_HOME, PWD},
+        feature::Feature,
+        profile::Profile,
+        store::{Object, SYSTEM_STORE, USER_STORE},
+        syscalls,
+    },
 };
 use anyhow::{Result, anyhow};
 use clap::ValueHint;
+use log::warn;
 use nix::unistd::getcwd;
+use spawn::Spawner;
 use std::{
+    borrow::Cow,
     fs::{self, File},
     io,
-    path::PathBuf,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
 };
 
+/// Arguments that refer to a file descriptor Antimony passes the sandbox, and how many
+/// arguments follow them. `--file` is handled separately, as its file can be bound instead.
+static FD_ARGS: [(&str, usize); 3] = [
+    ("--json-status-fd", 1),
+    ("--seccomp", 1),
+    ("--add-seccomp-fd", 1),
+];
+
+/// The bubblewrap the script runs, for both the sandbox and its proxies.
+static BWRAP: &str = "/usr/bin/bwrap";
+
 #[derive(clap::Args)]
 pub struct Args {
     /// The name of the profile/feature to export. If absent, export all user-profiles/features.
@@ -34,9 +57,45 @@ pub struct Args {
     /// Export the SECCOMP database. Overrides --feature and --system
     #[arg(long)]
     pub seccomp: bool,
+
+    /// Export the profile as a single archive, alongside the user features it uses,
+    /// its SECCOMP data, and its home. Restore it with `antimony import --with-home`.
+    #[arg(long, requires = "name", conflicts_with_all = ["feature", "seccomp"])]
+    pub with_home: bool,
+
+    /// Export the sandbox of the profile as a shell script that runs bubblewrap directly,
+    /// to inspect exactly what the profile exposes, or to run it without Antimony.
+    #[arg(long, requires = "name", conflicts_with_all = ["feature", "seccomp", "with_home"])]
+    pub script: bool,
 }
 impl super::Run for Args {
     fn run(self) -> Result<()> {
+        if self.script
+            && let Some(name) = &self.name
+        {
+            let dest = self.dest.map_or_else(|| PWD.clone(), PathBuf::from);
+            if !dest.exists() {
+                fs::create_dir_all(&dest)?;
+            }
+            let path = dest.join(format!("{name}.sh"));
+            self::script(name, &path)?;
+            println!("Exported to {}", path.display());
+            return Ok(());
+        }
+
+        if self.with_home
+            && let Some(name) = &self.name
+        {
+            let dest = self.dest.map_or_else(|| PWD.clone(), PathBuf::from);
+            if !dest.exists() {
+                fs::create_dir_all(&dest)?;
+            }
+            let archive = dest.join(format!("{name}.tar.zst"));
+            self::archive(name, &archive)?;
+            println!("Exported to {}", archive.display());
+            return Ok(());
+        }
+
         if self.seccomp {
             let db = AT_HOME.join("seccomp").join("syscalls.db");
             if db.exists() {
@@ -89,3 +148,213 @@ impl super::Run for Args {
         }
     }
 }
+
+/// Archive a profile, the user features it uses, its syscalls, and its home.
+fn archive(name: &str, dest: &Path) -> Result<()> {
+    let temp = temp::Builder::new()
+        .owner(user::Mode::Real)
+        .create::<temp::Directory>()?;
+    let root = temp.full();
+
+    let content = USER_STORE
+        .borrow()
+        .fetch(name, Object::Profile)
+        .or_else(|_| SYSTEM_STORE.borrow().fetch(name, Object::Profile))
+        .map_err(|_| anyhow!("No such profile: {name}"))?;
+    let profile: Profile = toml::from_str(&content)?;
+
+    let profiles = root.join("profiles");
+    fs::create_dir_all(&profiles)?;
+    fs::write(profiles.join(name).with_extension("toml"), &content)?;
+
+    // System features will exist on the other end, so only user features are included,
+    // alongside any they require.
+    let features = root.join("features");
+    fs::create_dir_all(&features)?;
+    let mut pending: Vec<String> = profile
+        .configuration
+        .values()
+        .flat_map(|config| config.features.iter())
+        .chain(profile.features.iter())
+        .cloned()
+        .collect();
+    let mut seen = Set::default();
+    while let Some(feature) = pending.pop() {
+        if !seen.insert(feature.clone()) {
+            continue;
+        }
+        if let Ok(content) = USER_STORE.borrow().fetch(&feature, Object::Feature) {
+            if let Ok(parsed) = toml::from_str::<Feature>(&content)
+                && let Some(requires) = parsed.requires
+            {
+                pending.extend(requires);
+            }
+            fs::write(features.join(&feature).with_extension("toml"), content)?;
+        }
+    }
+
+    match syscalls::export_profile(name) {
+        Ok(db) => fs::write(root.join("syscalls.db"), db)?,
+        Err(e) => warn!("Failed to export SECCOMP data: {e}"),
+    }
+
+    let mut args = vec![
+        "--zstd".to_owned(),
+        "-cpf".to_owned(),
+        dest.to_string_lossy().into_owned(),
+        "-C".to_owned(),
+        root.to_string_lossy().into_owned(),
+    ];
+    args.extend(
+        fs::read_dir(&root)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned()),
+    );
+
+    // The home is stored under home/, wherever it lives on this machine.
+    if let Some(home) = &profile.home
+        && let path = home.path(name)
+        && path.exists()
+    {
+        args.extend([
+            "-C".to_owned(),
+            path.to_string_lossy().into_owned(),
+            "--transform=s,^\\.,home,".to_owned(),
+            ".".to_owned(),
+        ]);
+    }
+
+    let code = Spawner::new("tar")?
+        .args(args)
+        .mode(user::Mode::Real)
+        .spawn()?
+        .wait()?;
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("tar failed with code {code}"))
+    }
+}
+
+/// Quote an argument for the shell.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Format a command as a line of the script. Files passed by descriptor are bound from
+/// where the descriptor points instead, and other arguments that refer to file descriptors,
+/// which only exist while Antimony runs the sandbox, are dropped.
+fn command(program: &str, handle: &Spawner, notes: &mut Vec<String>) -> String {
+    let args = handle.get_args();
+    let mut lines = vec![quote(program)];
+    let mut bound = Set::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--file"
+            && let (Some(fd), Some(dest)) = (args.next(), args.next())
+        {
+            // The descriptor is still held by the handle, so it still points to its file.
+            match fs::read_link(format!("/proc/self/fd/{fd}")) {
+                Ok(src) => {
+                    lines.extend([
+                        quote("--ro-bind"),
+                        quote(&src.to_string_lossy()),
+                        quote(dest),
+                    ]);
+                    bound.insert(dest.clone());
+                }
+                Err(_) => notes.push(format!(
+                    "{arg} {fd} {dest} was dropped, as it passes a file descriptor."
+                )),
+            }
+            continue;
+        }
+
+        // A bound file keeps the permissions it has on the host, and changing them would
+        // change the host's file.
+        if arg == "--chmod"
+            && let (Some(mode), Some(dest)) = (args.next(), args.next())
+        {
+            if !bound.contains(dest) {
+                lines.extend([quote(arg), quote(mode), quote(dest)]);
+            } else if mode == "555" {
+                notes.push(format!(
+                    "{dest} is bound from the host rather than copied, so it keeps the host's permissions, and won't run unless it is executable there."
+                ));
+            }
+            continue;
+        }
+
+        if let Some((_, count)) = FD_ARGS.iter().find(|(flag, _)| flag == arg) {
+            let dropped: Vec<&str> = args.by_ref().take(*count).map(String::as_str).collect();
+            notes.push(format!(
+                "{arg} {} was dropped, as it passes a file descriptor.",
+                dropped.join(" ")
+            ));
+            continue;
+        }
+        lines.push(quote(arg));
+    }
+    lines.join(" \\\n    ")
+}
+
+/// Write a shell script that runs the sandbox of a profile directly.
+fn script(name: &str, dest: &Path) -> Result<()> {
+    let mut args = run::Args {
+        profile: name.to_owned(),
+        dry: true,
+        record: true,
+        ..Default::default()
+    };
+    let mut info = setup::setup(Cow::Owned(name.to_owned()), &mut args, false, None)?;
+    run::command(&mut info)?;
+
+    let mut notes = vec![format!(
+        "Antimony creates {} for each instance, and removes it on exit. Binds from it must be recreated.",
+        info.instance.full().display()
+    )];
+    if info.profile.seccomp.is_some() {
+        notes.push("The SECCOMP filter is installed by Antimony, and is not included.".to_owned());
+    }
+    if !info.proxies.is_empty() {
+        notes.push(
+            "Portal permissions, the document portal, and the .flatpak-info are prepared by Antimony at launch, and are not included."
+                .to_owned(),
+        );
+    }
+    if info.profile.lockdown.unwrap_or(false) {
+        notes.push(
+            "The profile uses Lockdown, which takes over from bubblewrap. The script runs bubblewrap instead."
+                .to_owned(),
+        );
+    }
+    if info.profile.hooks.is_some() {
+        notes.push("Hooks are run by Antimony, and are not included.".to_owned());
+    }
+
+    let proxies: Vec<String> = info
+        .proxies
+        .iter()
+        .map(|proxy| command(BWRAP, proxy, &mut notes))
+        .collect();
+    let sandbox = command(BWRAP, &info.handle, &mut notes);
+
+    let mut script = vec![
+        "#!/bin/sh".to_owned(),
+        format!("# The sandbox of {name}, exported by Antimony."),
+        "#".to_owned(),
+    ];
+    script.extend(notes.iter().map(|note| format!("# * {note}")));
+    script.push(String::new());
+    if !proxies.is_empty() {
+        script.push("# The bus proxies run beside the sandbox.".to_owned());
+        script.extend(proxies.into_iter().map(|proxy| format!("{proxy} &")));
+        script.push(String::new());
+    }
+    script.push(format!("exec {sandbox} \\\n    \"$@\""));
+    script.push(String::new());
+
+    fs::write(dest, script.join("\n"))?;
+    fs::set_permissions(dest, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! View the revision history of user profiles/features.
+
+use crate::{
+    cli,
+    shared::store::{Object, USER_STORE, history},
+};
+use anyhow::{Result, anyhow};
+use clap::ValueHint;
+use dialoguer::console::style;
+
+#[derive(clap::Args, Default)]
+pub struct Args {
+    /// The name of the profile/feature.
+    #[arg(value_hint = ValueHint::CommandName)]
+    pub name: String,
+
+    /// Display the changes of a single revision.
+    pub rev: Option<usize>,
+
+    /// Target the feature set rather than the profile set.
+    #[arg(short, long)]
+    pub feature: bool,
+}
+impl cli::Run for Args {
+    fn run(self) -> Result<()> {
+        let (table, kind) = if self.feature {
+            (Object::Feature, "feature")
+        } else {
+            (Object::Profile, "profile")
+        };
+
+        let history = history::history(USER_STORE.borrow(), &self.name, table);
+        if history.revisions.is_empty() {
+            return Err(anyhow!("No history for {kind}: {}", self.name));
+        }
+
+        let print = |index: usize, revision: &history::Revision, full: bool| {
+            let diff = history.diff(index);
+            let state = if revision.content.is_none() {
+                " (Removed)"
+            } else {
+                ""
+            };
+            println!(
+                "{} {}{state}",
+                style(format!("[{index}]")).bold(),
+                style(revision.age()).italic()
+            );
+            if let Some(message) = &revision.message {
+                println!("\t{message}");
+            }
+
+            if full {
+                diff.lines().for_each(|line| {
+                    if line.starts_with('+') {
+                        println!("{}", style(line).green());
+                    } else if line.starts_with('-') {
+                        println!("{}", style(line).red());
+                    } else if !line.starts_with('@') {
+                        println!("{line}");
+                    }
+                });
+            } else {
+                let (added, removed) = diff.lines().fold((0usize, 0usize), |(a, r), line| {
+                    if line.starts_with("+++") || line.starts_with("---") {
+                        (a, r)
+                    } else if line.starts_with('+') {
+                        (a.saturating_add(1), r)
+                    } else if line.starts_with('-') {
+                        (a, r.saturating_add(1))
+                    } else {
+                        (a, r)
+                    }
+                });
+                println!(
+                    "\t{} {}",
+                    style(format!("+{added}")).green(),
+                    style(format!("-{removed}")).red()
+                );
+            }
+        };
+
+        if let Some(rev) = self.rev {
+            let Some(revision) = history.revisions.get(rev) else {
+                return Err(anyhow!("No such revision: {rev}"));
+            };
+            print(rev, revision, true);
+        } else {
+            println!("\n{}", style(format!("=== {} ===", self.name)).bold());
+            for (index, revision) in history.revisions.iter().enumerate().rev() {
+                print(index, revision, false);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Manage profile home directories.
+
+use crate::{
+    cli,
+    shared::{
+        profile::{
+            Profile,
+            home::{Home, parse_account},
+        },
+        snapshot::{self, Change},
+    },
+};
+use anyhow::{Result, anyhow};
+use clap::{Subcommand, ValueHint};
+use dialoguer::{Confirm, console::style};
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Action,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Take a snapshot of a profile's home.
+    Snapshot {
+        #[command(flatten)]
+        target: Target,
+
+        /// An optional label to identify the snapshot.
+        label: Option<String>,
+    },
+
+    /// List the snapshots of a profile's home.
+    List {
+        #[command(flatten)]
+        target: Target,
+    },
+
+    /// Restore a profile's home to a snapshot. The current home is snapshot first.
+    Restore {
+        #[command(flatten)]
+        target: Target,
+
+        /// The index or label of the snapshot.
+        snapshot: String,
+    },
+
+    /// Show what has changed in a profile's home since a snapshot.
+    Diff {
+        #[command(flatten)]
+        target: Target,
+
+        /// The index or label of the snapshot. Defaults to the most recent.
+        snapshot: Option<String>,
+    },
+
+    /// List, create or delete the accounts of a profile's home.
+    Accounts {
+        /// The name of the profile.
+        #[arg(value_hint = ValueHint::CommandName)]
+        profile: String,
+
+        /// Use the home of a configuration of the profile.
+        #[arg(short, long)]
+        config: Option<String>,
+
+        /// Create a new account.
+        #[arg(long, value_parser = parse_account, conflicts_with = "delete")]
+        create: Option<String>,
+
+        /// Delete an account, and its snapshots.
+        #[arg(long, value_parser = parse_account)]
+        delete: Option<String>,
+
+        /// Don't ask for confirmation when deleting.
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+/// The home to operate on.
+#[derive(clap::Args)]
+pub struct Target {
+    /// The name of the profile.
+    #[arg(value_hint = ValueHint::CommandName)]
+    pub profile: String,
+
+    /// Use the home of a configuration of the profile.
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Use an account of the home.
+    #[arg(short, long, value_parser = parse_account)]
+    pub account: Option<String>,
+}
+impl Target {
+    /// Get the home, and its snapshot directory.
+    fn resolve(&self) -> Result<(PathBuf, PathBuf)> {
+        let mut home = home(&self.profile, self.config.clone())?;
+        if self.account.is_some() {
+            home.account.clone_from(&self.account);
+        }
+        Ok((home.path(&self.profile), home.snapshots(&self.profile)))
+    }
+}
+
+/// Get the home of a profile.
+fn home(name: &str, config: Option<String>) -> Result<Home> {
+    let (profile, _) = Profile::new(name, config, None, false)?;
+    profile
+        .home
+        .ok_or_else(|| anyhow!("{name} does not have a home"))
+}
+
+impl cli::Run for Args {
+    fn run(self) -> Result<()> {
+        match self.command {
+            Action::Snapshot { target, label } => {
+                let (home, dir) = target.resolve()?;
+                let snapshot = snapshot::take(&home, &dir, label.as_deref())?;
+                println!("Created snapshot {}", snapshot.path.display());
+            }
+
+            Action::List { target } => {
+                let (_, dir) = target.resolve()?;
+                let snapshots = snapshot::list(&dir);
+                if snapshots.is_empty() {
+                    return Err(anyhow!("No snapshots for {}", target.profile));
+                }
+
+                println!("\n{}", style(format!("=== {} ===", target.profile)).bold());
+                for (index, snapshot) in snapshots.iter().enumerate().rev() {
+                    let kind = if snapshot.archive {
+                        "Archive"
+                    } else {
+                        "Reflink"
+                    };
+                    println!(
+                        "{} {} {} {}",
+                        style(format!("[{index}]")).bold(),
+                        snapshot.label.as_deref().unwrap_or_default(),
+                        style(snapshot.age()).italic(),
+                        style(format!("({kind})")).dim()
+                    );
+                }
+            }
+
+            Action::Restore { target, snapshot } => {
+                let (home, dir) = target.resolve()?;
+                let snapshot = snapshot::find(&dir, &snapshot)?;
+                snapshot::restore(&home, &dir, &snapshot)?;
+                println!(
+                    "Restored {} to the snapshot from {}",
+                    target.profile,
+                    snapshot.age()
+                );
+            }
+
+            Action::Diff { target, snapshot } => {
+                let (home, dir) = target.resolve()?;
+                let snapshot = match snapshot {
+                    Some(id) => snapshot::find(&dir, &id)?,
+                    None => snapshot::list(&dir)
+                        .pop()
+                        .ok_or_else(|| anyhow!("No snapshots for {}", target.profile))?,
+                };
+
+                for (change, path) in snapshot::diff(&home, &snapshot)? {
+                    match change {
+                        Change::Added => println!("{}", style(format!("+ {path}")).green()),
+                        Change::Removed => println!("{}", style(format!("- {path}")).red()),
+                        Change::Modified => println!("{}", style(format!("~ {path}")).yellow()),
+                    }
+                }
+            }
+
+            Action::Accounts {
+                profile,
+                config,
+                create,
+                delete,
+                yes,
+            } => {
+                let mut home = home(&profile, config)?;
+                if let Some(account) = create {
+                    home.account = Some(account);
+                    let path = home.path(&profile);
+                    if path.exists() {
+                        return Err(anyhow!("{} already exists", path.display()));
+                    }
+                    fs::create_dir_all(&path)?;
+                    println!("Created account at {}", path.display());
+                } else if let Some(account) = delete {
+                    home.account = Some(account);
+                    let path = home.path(&profile);
+                    if !path.exists() {
+                        return Err(anyhow!("{} does not exist", path.display()));
+                    }
+
+                    // An instance holding the lock is still using the account.
+                    if File::open(&path)?.try_lock().is_err() {
+                        return Err(anyhow!("{} is in use", path.display()));
+                    }
+
+                    if yes
+                        || Confirm::new()
+                            .with_prompt(format!(
+                                "Are you sure you want to delete {}? This cannot be undone.",
+                                path.display()
+                            ))
+                            .interact()?
+                    {
+                        fs::remove_dir_all(&path)?;
+                        let snapshots = home.snapshots(&profile);
+                        if snapshots.exists() {
+                            fs::remove_dir_all(snapshots)?;
+                        }
+                        println!("Deleted {}", path.display());
+                    }
+                } else {
+                    let accounts = home.accounts(&profile);
+                    if accounts.is_empty() {
+                        return Err(anyhow!("{profile} has no accounts"));
+                    }
+                    println!("\n{}", style(format!("=== {profile} ===")).bold());
+                    for account in accounts {
+                        println!("{account}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,19 @@
 //! Import user-profiles
 
 use crate::shared::{
+    env::DATA_HOME,
     feature::{self, Feature},
-    privileged,
+    firejail, flatpak, privileged,
     profile::{self, Profile},
     store::{self, Object},
     syscalls,
 };
 use anyhow::anyhow;
 use clap::ValueHint;
+use dialoguer::{Confirm, console::style};
 use log::warn;
-use std::path::Path;
+use spawn::Spawner;
+use std::{fs, path::Path};
 
 #[derive(clap::Args)]
 pub struct Args {
@@ -29,9 +32,36 @@ pub struct Args {
     /// Import data into the SECCOMP database. Overrides --feature and --system.
     #[arg(long)]
     pub seccomp: bool,
+
+    /// Import an archive made by `antimony export --with-home`.
+    #[arg(long, conflicts_with_all = ["feature", "system", "seccomp"])]
+    pub with_home: bool,
+
+    /// Overwrite existing user features with those in the archive without asking.
+    #[arg(short, long, requires = "with_home")]
+    pub yes: bool,
+
+    /// Convert the permissions of a Flatpak manifest, or the metadata of an installed
+    /// Flatpak, into a new profile.
+    #[arg(long, conflicts_with_all = ["feature", "seccomp", "with_home"])]
+    pub flatpak: bool,
+
+    /// Convert a Firejail profile, following its includes, into a new profile.
+    #[arg(long, conflicts_with_all = ["feature", "seccomp", "with_home", "flatpak"])]
+    pub firejail: bool,
 }
 impl super::Run for Args {
     fn run(self) -> anyhow::Result<()> {
+        if self.with_home {
+            return unarchive(Path::new(&self.name), self.yes);
+        }
+        if self.flatpak {
+            return create(flatpak::convert(Path::new(&self.name))?, self.system);
+        }
+        if self.firejail {
+            return create(firejail::convert(Path::new(&self.name))?, self.system);
+        }
+
         if self.seccomp {
             if privileged()? {
                 return syscalls::merge_database(Path::new(&self.name));
@@ -87,3 +117,166 @@ impl super::Run for Args {
         }
     }
 }
+
+/// Store a profile converted from another sandbox, and list what could not be translated.
+fn create(
+    (name, profile, untranslated): (String, Profile, Vec<String>),
+    system: bool,
+) -> anyhow::Result<()> {
+    let store = if system {
+        if !privileged()? {
+            return Err(anyhow!("Not allowed to modify system store"));
+        }
+        store::SYSTEM_STORE.borrow()
+    } else {
+        store::USER_STORE.borrow()
+    };
+    if store.exists(&name, Object::Profile) {
+        return Err(anyhow!(
+            "A profile named {name} already exists. Remove it, or edit it instead."
+        ));
+    }
+    store.store(&name, Object::Profile, &toml::to_string(&profile)?)?;
+    println!("Created profile {}", style(&name).bold());
+
+    if !untranslated.is_empty() {
+        println!(
+            "\n{}",
+            style("=== Permissions that were not translated ===").bold()
+        );
+        for arg in untranslated {
+            println!("\t - {}", style(arg).italic());
+        }
+    }
+    Ok(())
+}
+
+/// Import an archived profile, its features, syscalls and home.
+fn unarchive(archive: &Path, yes: bool) -> anyhow::Result<()> {
+    // Extract beside the homes, so the home can simply be moved into place.
+    let antimony = DATA_HOME.join("antimony");
+    fs::create_dir_all(&antimony)?;
+    let temp = temp::Builder::new()
+        .within(antimony.clone())
+        .owner(user::Mode::Real)
+        .create::<temp::Directory>()?;
+    let root = temp.full();
+
+    let code = Spawner::new("tar")?
+        .args([
+            "--zstd",
+            "-xpf",
+            &archive.to_string_lossy(),
+            "-C",
+            &root.to_string_lossy(),
+        ])
+        .mode(user::Mode::Real)
+        .spawn()?
+        .wait()?;
+    if code != 0 {
+        return Err(anyhow!("tar failed with code {code}"));
+    }
+
+    let mut profiles = root.join("profiles").read_dir()?.filter_map(Result::ok);
+    let (Some(entry), None) = (profiles.next(), profiles.next()) else {
+        return Err(anyhow!("{} is not a profile archive", archive.display()));
+    };
+    let path = entry.path();
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("Invalid profile in archive"))?;
+    let profile =
+        store::load::<Profile, profile::Error>(&path.to_string_lossy(), Object::Profile, true)?;
+
+    let user = store::USER_STORE.borrow();
+    if user.exists(&name, Object::Profile) {
+        return Err(anyhow!(
+            "A profile named {name} already exists. Remove it to import the archive."
+        ));
+    }
+
+    // Refuse to clobber an existing home before anything is imported.
+    let dest = profile.home.as_ref().map(|home| home.path(&name));
+    let archived = root.join("home");
+    if archived.exists()
+        && let Some(dest) = &dest
+        && dest
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(anyhow!(
+            "{} already exists. Move it aside to import the archived home",
+            dest.display()
+        ));
+    }
+
+    // Decide which features to import before importing anything, as a user feature
+    // that differs from the archived one is only replaced if the user agrees.
+    let mut features = Vec::new();
+    if let Ok(entries) = root.join("features").read_dir() {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let Some(stem) = path.file_stem() else {
+                continue;
+            };
+            let feature = stem.to_string_lossy().into_owned();
+            let content = toml::to_string(&store::load::<Feature, feature::Error>(
+                &path.to_string_lossy(),
+                Object::Feature,
+                true,
+            )?)?;
+
+            if let Ok(existing) = user.fetch(&feature, Object::Feature) {
+                if existing == content {
+                    continue;
+                }
+                if !yes && !Confirm::new()
+                    .with_prompt(format!(
+                        "The user feature {feature} differs from the archived one. Overwrite it?"
+                    ))
+                    .default(false)
+                    .interact()?
+                {
+                    println!("Kept feature {feature}");
+                    continue;
+                }
+            }
+            features.push((feature, content));
+        }
+    }
+
+    for (feature, content) in features {
+        user.store(&feature, Object::Feature, &content)?;
+        println!("Imported feature {feature}");
+    }
+
+    user.store(&name, Object::Profile, &toml::to_string(&profile)?)?;
+    println!("Imported profile {name}");
+
+    let db = root.join("syscalls.db");
+    if db.exists() {
+        if privileged()? {
+            syscalls::merge_database(&db)?;
+            println!("Imported SECCOMP data");
+        } else {
+            warn!(
+                "Modifying the SECCOMP database is a privileged operation. SECCOMP data was not imported"
+            );
+        }
+    }
+
+    if archived.exists()
+        && let Some(dest) = dest
+    {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if dest.exists() {
+            fs::remove_dir(&dest)?;
+        }
+        fs::rename(&archived, &dest)?;
+        println!("Imported home to {}", dest.display());
+    }
+    Ok(())
+}
@@ -3,7 +3,8 @@
 use crate::{
     cli,
     shared::{
-        Set,
+        Set, permissions,
+        profile::{Profile, ipc::Portal},
         store::{Object, SYSTEM_STORE, USER_STORE},
         syscalls,
     },
@@ -37,11 +38,25 @@ pub struct Args {
 
     #[arg(long)]
     pub seccomp: bool,
+
+    /// Display the portal permissions granted to the profile. Overrides --feature, --system, and --diff.
+    #[arg(long, requires = "name")]
+    pub portals: bool,
+
+    /// Revoke portal permissions, such that the portal asks again.
+    #[arg(long, requires = "portals", value_delimiter = ' ', num_args = 1..)]
+    pub revoke: Option<Vec<Portal>>,
 }
 
 impl cli::Run for Args {
     #[allow(clippy::too_many_lines)]
     fn run(self) -> Result<()> {
+        if self.portals
+            && let Some(name) = &self.name
+        {
+            return portals(name, self.revoke.unwrap_or_default());
+        }
+
         if self.seccomp {
             match self.name {
                 // Get Profile/Binary information depending on a path.
@@ -199,3 +214,37 @@ impl cli::Run for Args {
         Ok(())
     }
 }
+
+/// Display, and revoke, the permissions a profile holds in the permission store.
+fn portals(name: &str, revoke: Vec<Portal>) -> Result<()> {
+    let (profile, _) = Profile::new(name, None, None, true)?;
+    let id = profile.id(name);
+
+    for portal in revoke {
+        permissions::revoke(&id, &portal)?;
+        println!("Revoked {}", style(portal).bold());
+    }
+
+    let configured = profile
+        .ipc
+        .map(|ipc| ipc.portal_permissions)
+        .unwrap_or_default();
+
+    println!("\n{}", style(format!("=== {id} ===")).bold());
+    for (portal, permission) in permissions::get(&id)? {
+        let current = permission.map_or_else(
+            || style("unset".to_owned()).dim(),
+            |permission| style(permission.join(" ")).green(),
+        );
+        if let Some(setting) = configured.get(&portal) {
+            println!(
+                "{} => {current} (Profile sets {})",
+                style(&portal).italic().magenta(),
+                style(setting).bold()
+            );
+        } else {
+            println!("{} => {current}", style(&portal).italic().magenta());
+        }
+    }
+    Ok(())
+}
@@ -3,21 +3,26 @@
 use crate::{
     cli::{self, Cli, Command},
     shared::{
+        Map, Set,
         env::{CONFIG_HOME, DATA_HOME, HOME_PATH, SESSION_BUS},
         profile::Profile,
+        store::{Object, SYSTEM_STORE, USER_STORE},
     },
 };
 use anyhow::{Context, Result, anyhow};
 use clap::{Parser, ValueEnum, ValueHint};
+use dialoguer::console::style;
 use heck::ToTitleCase;
-use log::info;
+use inotify::{Inotify, WatchMask};
+use log::{info, warn};
 use spawn::Spawner;
 use std::{
+    collections::BTreeMap,
     fmt::Write as FormatWrite,
     fs::{self, File},
     io::Write as IoWrite,
     os::unix::fs::symlink,
-    path::Path,
+    path::{Path, PathBuf},
 };
 use user::{Mode, USER};
 
@@ -41,9 +46,18 @@ pub fn integrate_vec(profile: &str, mut passthrough: Vec<String>) -> self::Args
 #[derive(clap::Args, Default)]
 pub struct Args {
     /// The name of the profile
-    #[arg(value_hint = ValueHint::CommandName)]
+    #[arg(value_hint = ValueHint::CommandName, default_value = "", hide_default_value = true)]
     pub profile: String,
 
+    /// Integrate every installed application that has a profile, and list those that don't.
+    #[arg(long, conflicts_with_all = ["remove", "default_for"])]
+    pub all: bool,
+
+    /// With --all, keep running, and integrate applications again whenever their desktop
+    /// files change, such as when they are updated.
+    #[arg(short, long, requires = "all")]
+    pub watch: bool,
+
     /// Undo integration for the profile.
     #[arg(short, long)]
     pub remove: bool,
@@ -87,8 +101,27 @@ pub struct Args {
     /// Overwrite a desktop file if it already exists.
     #[arg(short, long)]
     pub overwrite: bool,
+
+    /// Make the profile the default application for the MIME types and URL schemes
+    /// its desktop file lists. Provide a configuration, as `--default-for=CONFIG`, to make
+    /// it the default instead, which requires `--config-mode file`. The previous defaults
+    /// are restored on removal.
+    #[arg(long, num_args = 0..=1, require_equals = true, value_name = "CONFIG")]
+    pub default_for: Option<Option<String>>,
+
+    /// The desktop file of the application, if it was found outside `/usr/share/applications`.
+    #[arg(skip)]
+    pub desktop_file: Option<PathBuf>,
+
+    /// Whether this is part of `--all`, which marks the desktop file with `BULK_MARKER`.
+    #[arg(skip)]
+    pub bulk: bool,
 }
 
+/// Marks a desktop file written by `--all`, which later bulk integrations may replace.
+/// Anything else was integrated by hand, and keeps the options it was integrated with.
+static BULK_MARKER: &str = "X-Antimony-Bulk=true";
+
 #[derive(Default, ValueEnum, Copy, Clone, PartialEq, Eq)]
 pub enum ConfigMode {
     ///Integrate each configuration as a separate desktop action
@@ -104,6 +137,12 @@ pub enum ConfigMode {
 
 impl cli::Run for Args {
     fn run(self) -> Result<()> {
+        if self.all {
+            return integrate_all(&self);
+        } else if self.profile.is_empty() {
+            return Err(anyhow!("Provide a profile to integrate, or --all"));
+        }
+
         if self.remove {
             let mut profile = match Profile::new(&self.profile, None, None, true) {
                 Ok(profile) => profile.0,
@@ -114,13 +153,15 @@ impl cli::Run for Args {
             };
 
             // Load directly, since we can remove profiles that don't exist
+            restore_defaults(&self.profile)?;
             remove(&mut profile, &self)
         } else {
-            integrate(
-                &mut Profile::new(&self.profile, None, None, false)?.0,
-                &self,
-                false,
-            )
+            let mut profile = Profile::new(&self.profile, None, None, false)?.0;
+            integrate(&mut profile.clone(), &self, false)?;
+            if let Some(config) = &self.default_for {
+                set_defaults(&profile, &self, config.as_deref())?;
+            }
+            Ok(())
         }
     }
 }
@@ -190,6 +231,13 @@ pub fn remove(profile: &mut Profile, cmd: &Args) -> Result<()> {
         }
     }
 
+    for service in shadowed_services(&cmd.profile) {
+        match fs::remove_file(&service) {
+            Err(e) => eprintln!("{}: {e}.", service.display()),
+            Ok(()) => println!("Removed D-Bus service shadow"),
+        }
+    }
+
     let copy = DATA_HOME
         .join("applications")
         .join(format!("{name}.desktop"));
@@ -305,6 +353,297 @@ fn manage_configurations(
     Ok(())
 }
 
+/// The accounts of the profile's home.
+fn accounts(profile: &Profile, cmd: &Args) -> Vec<String> {
+    profile
+        .home
+        .as_ref()
+        .map(|home| home.accounts(&cmd.profile))
+        .unwrap_or_default()
+}
+
+/// Add a desktop action for each account of the profile's home.
+fn manage_accounts(
+    contents: &mut Vec<String>,
+    cmd: &Args,
+    accounts: &[String],
+    local: &str,
+    package: bool,
+) {
+    for account in accounts {
+        if package {
+            contents.push(format!(
+                "[Desktop Action account-{account}]\n\
+                    Name=Antimony {} Account \n\
+                    Exec={local} --account {account} %U \n",
+                account.to_title_case(),
+            ));
+        } else {
+            contents.push(format!(
+                "[Desktop Action account-{account}]\n\
+                Name=Antimony {} Account \n\
+                Exec=antimony run {} --account {account} -- %U \n",
+                account.to_title_case(),
+                cmd.profile
+            ));
+        }
+    }
+}
+
+/// Where the user's default applications are recorded.
+fn mimeapps() -> PathBuf {
+    CONFIG_HOME.join("mimeapps.list")
+}
+
+/// Where the defaults a profile replaced are kept. The first line is the desktop file
+/// the profile was registered under, and the rest are the previous defaults.
+fn mimeapps_backup(name: &str) -> PathBuf {
+    DATA_HOME
+        .join("antimony")
+        .join("mimeapps")
+        .join(format!("{name}.list"))
+}
+
+/// Update the default applications in mimeapps.list. Each MIME type is set to its
+/// value, or removed if there is none, but only if `current` says it should be.
+/// Returns the previous defaults of the MIME types that were updated.
+fn update_defaults(
+    defaults: &BTreeMap<String, Option<String>>,
+    current: impl Fn(Option<&str>) -> bool,
+) -> Result<BTreeMap<String, Option<String>>> {
+    let path = mimeapps();
+    let contents = fs::read_to_string(&path).unwrap_or_default();
+
+    let mut previous = BTreeMap::new();
+    let mut lines: Vec<String> = Vec::new();
+    let mut section = false;
+    let mut found = false;
+
+    // Append the defaults that weren't already present to the section.
+    let append = |lines: &mut Vec<String>, previous: &mut BTreeMap<_, _>| {
+        for (mime, value) in defaults {
+            if !previous.contains_key(mime)
+                && current(None)
+                && let Some(value) = value
+            {
+                lines.push(format!("{mime}={value}"));
+                previous.insert(mime.clone(), None);
+            }
+        }
+    };
+
+    for line in contents.lines() {
+        if line.starts_with('[') {
+            if section {
+                append(&mut lines, &mut previous);
+            }
+            section = line.trim() == "[Default Applications]";
+            found |= section;
+        } else if section
+            && let Some((mime, value)) = line.split_once('=')
+            && let Some(default) = defaults.get(mime.trim())
+            && current(Some(value.trim()))
+        {
+            previous.insert(mime.trim().to_owned(), Some(value.trim().to_owned()));
+            if let Some(default) = default {
+                lines.push(format!("{}={default}", mime.trim()));
+            }
+            continue;
+        }
+        lines.push(line.to_owned());
+    }
+
+    if section {
+        append(&mut lines, &mut previous);
+    } else if !found && defaults.values().any(Option::is_some) {
+        lines.push("[Default Applications]".to_owned());
+        append(&mut lines, &mut previous);
+    }
+
+    if let Some(parent) = path.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, lines.join("\n") + "\n")?;
+    Ok(previous)
+}
+
+/// Register the profile, or one of its configurations, as the default application
+/// for everything its desktop file handles.
+fn set_defaults(profile: &Profile, cmd: &Args, config: Option<&str>) -> Result<()> {
+    let name = &cmd.profile;
+    let applications = DATA_HOME.join("applications");
+
+    let base = [
+        profile.id(name),
+        profile.desktop(name).into_owned(),
+        name.clone(),
+    ]
+    .into_iter()
+    .find(|base| applications.join(format!("{base}.desktop")).exists())
+    .ok_or_else(|| anyhow!("{name} has no integrated desktop file"))?;
+
+    let desktop = match config {
+        Some(config) => {
+            if cmd.config_mode != Some(ConfigMode::File) {
+                return Err(anyhow!(
+                    "Configurations need their own desktop file to be a default. Use --config-mode file"
+                ));
+            }
+            format!("{base}-{config}.desktop")
+        }
+        None => format!("{base}.desktop"),
+    };
+
+    let contents = fs::read_to_string(applications.join(&desktop))
+        .with_context(|| format!("Failed to read {desktop}"))?;
+    let defaults: BTreeMap<String, Option<String>> = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("MimeType="))
+        .unwrap_or_default()
+        .split(';')
+        .filter(|mime| !mime.is_empty())
+        .map(|mime| (mime.to_owned(), Some(format!("{desktop};"))))
+        .collect();
+
+    if defaults.is_empty() {
+        println!("{desktop} does not handle any MIME types");
+        return Ok(());
+    }
+
+    // Keep the original defaults if the profile has already been registered.
+    let backup = mimeapps_backup(name);
+    let mut previous: BTreeMap<String, Option<String>> = BTreeMap::new();
+    if let Ok(contents) = fs::read_to_string(&backup) {
+        for line in contents.lines().skip(1) {
+            if let Some((mime, value)) = line.split_once('=') {
+                previous.insert(
+                    mime.to_owned(),
+                    Some(value.to_owned()).filter(|v| !v.is_empty()),
+                );
+            }
+        }
+    }
+
+    for (mime, value) in update_defaults(&defaults, |_| true)? {
+        previous.entry(mime).or_insert(value);
+    }
+
+    let mut lines = vec![format!("{desktop};")];
+    for (mime, value) in &previous {
+        lines.push(format!("{mime}={}", value.as_deref().unwrap_or_default()));
+    }
+    if let Some(parent) = backup.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(backup, lines.join("\n"))?;
+
+    println!("{desktop} is now the default for {} types", defaults.len());
+    Ok(())
+}
+
+/// Restore the defaults a profile replaced, so long as it's still the default.
+fn restore_defaults(name: &str) -> Result<()> {
+    let backup = mimeapps_backup(name);
+    let Ok(contents) = fs::read_to_string(&backup) else {
+        return Ok(());
+    };
+
+    let mut lines = contents.lines();
+    let desktop = lines.next().unwrap_or_default().to_owned();
+    let previous: BTreeMap<String, Option<String>> = lines
+        .filter_map(|line| line.split_once('='))
+        .map(|(mime, value)| {
+            (
+                mime.to_owned(),
+                Some(value.to_owned()).filter(|v| !v.is_empty()),
+            )
+        })
+        .collect();
+
+    update_defaults(&previous, |current| current == Some(desktop.as_str()))?;
+    fs::remove_file(backup)?;
+    println!("Restored default applications");
+    Ok(())
+}
+
+/// Where the user's D-Bus services live. They take precedence over the system's.
+fn user_services() -> PathBuf {
+    DATA_HOME.join("dbus-1").join("services")
+}
+
+/// The program a D-Bus service file executes.
+fn service_exec(contents: &str) -> Option<&str> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("Exec="))
+        .and_then(|exec| exec.split_whitespace().next())
+}
+
+/// The user D-Bus services that have been shadowed to run the profile through Antimony.
+fn shadowed_services(name: &str) -> Vec<PathBuf> {
+    let local = HOME_PATH.join(".local").join("bin").join(name);
+    let local = local.to_string_lossy();
+    let Ok(entries) = fs::read_dir(user_services()) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            fs::read_to_string(path)
+                .is_ok_and(|contents| service_exec(&contents) == Some(local.as_ref()))
+        })
+        .collect()
+}
+
+/// Shadow the system's D-Bus services that run the profile's binary, such that activating
+/// the application over the bus runs it through Antimony, rather than unconfined.
+fn manage_services(profile: &Profile, name: &str, local: &str) -> Result<()> {
+    let Ok(binary) = fs::canonicalize(profile.app_path(name).as_ref()) else {
+        return Ok(());
+    };
+    let Ok(entries) = fs::read_dir("/usr/share/dbus-1/services") else {
+        return Ok(());
+    };
+
+    for entry in entries.flatten() {
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let Some(exec) = service_exec(&contents) else {
+            continue;
+        };
+        if fs::canonicalize(exec).ok().as_ref() != Some(&binary) {
+            continue;
+        }
+
+        info!("Shadowing D-Bus service {}", entry.path().display());
+
+        // A systemd unit would be run in place of Exec, so it must go.
+        let shadow: Vec<String> = contents
+            .lines()
+            .filter(|line| !line.starts_with("SystemdService="))
+            .map(|line| {
+                if line.starts_with("Exec=") {
+                    line.replacen(exec, local, 1)
+                } else {
+                    line.to_owned()
+                }
+            })
+            .collect();
+
+        let services = user_services();
+        if !services.exists() {
+            fs::create_dir_all(&services)?;
+        }
+        fs::write(services.join(entry.file_name()), shadow.join("\n"))?;
+    }
+    Ok(())
+}
+
 /// Make a shadow for a desktop file. By adding `NoDisplay`, we hide it from desktop environments.
 /// This is used it two ways:
 /// 1. For DEs that use the ID to source file icons (i.e GNOME), we need to create an `antimony.desktop`
@@ -372,16 +711,25 @@ pub fn format_desktop(
     info!("Creating desktop file from {}", desktop_file.display());
 
     let desktop_actions = desktop.contains("Actions=");
+    let accounts = accounts(profile, cmd);
     let append_actions = |line: &mut String| {
         for name in profile.configuration.keys() {
             let _ = write!(line, "{name};");
         }
+        for account in &accounts {
+            let _ = write!(line, "account-{account};");
+        }
 
         line.push_str("native;");
     };
 
     let mut contents: Vec<String> = desktop.lines().map(ToOwned::to_owned).collect();
     for line in &mut contents {
+        if cmd.bulk && line == "[Desktop Entry]" {
+            line.push('\n');
+            line.push_str(BULK_MARKER);
+        }
+
         // Point to the symlink
         if line.starts_with("Exec=") {
             fix_exec("Exec", local, line, None, cmd, package);
@@ -418,8 +766,9 @@ pub fn format_desktop(
         ));
     }
 
-    // Add configurations.
+    // Add configurations and accounts.
     manage_configurations(&mut contents, cmd, profile, &name, local, package)?;
+    manage_accounts(&mut contents, cmd, &accounts, local, package);
 
     if let Some(parent) = antimony_desktop.parent()
         && !parent.exists()
@@ -465,10 +814,14 @@ pub fn integrate(profile: &mut Profile, cmd: &Args, package: bool) -> Result<()>
     }
     let local = local.to_string_lossy();
 
-    let desktop_file = Path::new("/usr")
-        .join("share")
-        .join("applications")
-        .join(format!("{}.desktop", profile.desktop(name)));
+    // Configurations with their own ID have their own desktop file.
+    let desktop = format!("{}.desktop", profile.desktop(name));
+    let desktop_file = cmd.desktop_file.clone().unwrap_or_else(|| {
+        Path::new("/usr")
+            .join("share")
+            .join("applications")
+            .join(&desktop)
+    });
     if desktop_file.exists() {
         format_desktop(
             cmd,
@@ -496,6 +849,10 @@ pub fn integrate(profile: &mut Profile, cmd: &Args, package: bool) -> Result<()>
                 let _ = write!(actions, "{config};");
             }
         }
+        let accounts = accounts(profile, cmd);
+        for account in &accounts {
+            let _ = write!(actions, "account-{account};");
+        }
         contents.push(actions);
 
         contents.extend([
@@ -514,9 +871,12 @@ pub fn integrate(profile: &mut Profile, cmd: &Args, package: bool) -> Result<()>
             fs::create_dir_all(parent)?;
         }
         manage_configurations(&mut contents, cmd, profile, name, &local, package)?;
+        manage_accounts(&mut contents, cmd, &accounts, &local, package);
         fs::write(out, contents.join("\n"))?;
     }
 
+    manage_services(profile, name, &local)?;
+
     if cmd.autostart {
         let autostart_name = format!("{}.desktop", profile.desktop(name));
         let service_file = Path::new("/etc")
@@ -620,3 +980,167 @@ pub fn integrate(profile: &mut Profile, cmd: &Args, package: bool) -> Result<()>
 
     Ok(())
 }
+
+/// Where installed applications put their desktop files.
+fn application_dirs() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from("/usr/share/applications"),
+        PathBuf::from("/var/lib/flatpak/exports/share/applications"),
+        DATA_HOME
+            .join("flatpak")
+            .join("exports")
+            .join("share")
+            .join("applications"),
+    ]
+}
+
+/// Every profile, keyed by the desktop file names it would integrate.
+fn desktop_profiles() -> Vec<(String, Set<String>)> {
+    let mut names = SYSTEM_STORE
+        .borrow()
+        .get(Object::Profile)
+        .unwrap_or_default();
+    if let Ok(user) = USER_STORE.borrow().get(Object::Profile) {
+        names.extend(user);
+    }
+
+    names
+        .into_iter()
+        .filter(|name| name != "default")
+        .filter_map(|name| {
+            let (profile, _) = Profile::new(&name, None, None, true).ok()?;
+            let desktops = [profile.id(&name), profile.desktop(&name).into_owned()]
+                .into_iter()
+                .collect();
+            Some((name, desktops))
+        })
+        .collect()
+}
+
+/// The name of a visible desktop file, if it is one.
+fn visible_application(path: &Path) -> Option<String> {
+    if path.extension().is_some_and(|ext| ext == "desktop")
+        && fs::read_to_string(path).is_ok_and(|contents| !contents.contains("NoDisplay=true"))
+    {
+        Some(path.file_stem()?.to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
+/// The visible desktop files of installed applications, by name. Where several
+/// directories have the same application, the first takes precedence.
+fn installed_applications(dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut applications: Vec<(String, PathBuf)> = dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.path())
+        .filter_map(|path| Some((visible_application(&path)?, path)))
+        .collect();
+    applications.sort_by(|a, b| a.0.cmp(&b.0));
+    applications.dedup_by(|a, b| a.0 == b.0);
+    applications
+}
+
+/// Integrate a single profile as part of a bulk integration. A profile that was integrated
+/// by hand is left alone, so that it keeps the options it was integrated with.
+fn integrate_one(name: &str, desktop_file: &Path, cmd: &Args) {
+    let mut profile = match Profile::new(name, None, None, false) {
+        Ok((profile, _)) => profile,
+        Err(e) => {
+            warn!("Failed to load {name}: {e}");
+            return;
+        }
+    };
+
+    let applications = DATA_HOME.join("applications");
+    if [profile.desktop(name).into_owned(), profile.id(name)]
+        .into_iter()
+        .map(|desktop| applications.join(format!("{desktop}.desktop")))
+        .filter(|path| path.exists())
+        .any(|path| !fs::read_to_string(path).is_ok_and(|contents| contents.contains(BULK_MARKER)))
+    {
+        println!(
+            "Skipped {}, which was integrated by hand",
+            style(name).bold()
+        );
+        return;
+    }
+
+    let args = Args {
+        profile: name.to_owned(),
+        shadow: cmd.shadow,
+        config_mode: cmd.config_mode,
+        overwrite: true,
+        desktop_file: Some(desktop_file.to_path_buf()),
+        bulk: true,
+        ..Default::default()
+    };
+    match integrate(&mut profile, &args, false) {
+        Ok(()) => println!("Integrated {}", style(name).bold()),
+        Err(e) => warn!("Failed to integrate {name}: {e}"),
+    }
+}
+
+/// Integrate every installed application with a profile.
+fn integrate_all(cmd: &Args) -> Result<()> {
+    let dirs = application_dirs();
+    let profiles = desktop_profiles();
+    let matching = |application: &str| {
+        profiles
+            .iter()
+            .find(|(_, desktops)| desktops.contains(application))
+            .map(|(name, _)| name.as_str())
+    };
+
+    let mut missing = Vec::new();
+    for (application, path) in installed_applications(&dirs) {
+        match matching(&application) {
+            Some(name) => integrate_one(name, &path, cmd),
+            None => missing.push(application),
+        }
+    }
+
+    if !missing.is_empty() {
+        println!(
+            "\n{}",
+            style("=== Applications without a profile ===").bold()
+        );
+        for application in missing {
+            println!("\t - {}", style(application).italic());
+        }
+    }
+
+    if !cmd.watch {
+        return Ok(());
+    }
+
+    // Package managers replace desktop files on update, which would drop the shadow's changes.
+    let mut inotify = Inotify::init()?;
+    let mut watched = Map::default();
+    for dir in dirs.iter().filter(|dir| dir.exists()) {
+        let wd = inotify
+            .watches()
+            .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+        watched.insert(wd, dir);
+    }
+
+    println!("Watching for changes to installed applications");
+    let mut buffer = [0; 4096];
+    loop {
+        let changed: Map<String, PathBuf> = inotify
+            .read_events_blocking(&mut buffer)?
+            .filter_map(|event| {
+                let path = watched.get(&event.wd)?.join(event.name?);
+                Some((visible_application(&path)?, path))
+            })
+            .collect();
+
+        for (application, path) in changed {
+            if let Some(name) = matching(&application) {
+                integrate_one(name, &path, cmd);
+            }
+        }
+    }
+}
@@ -2,13 +2,18 @@
 
 pub mod edit;
 pub mod export;
+pub mod history;
+pub mod home;
 pub mod import;
 pub mod info;
 pub mod integrate;
 pub mod package;
 pub mod refresh;
 pub mod remove;
+pub mod revert;
 pub mod run;
+pub mod store;
+pub mod trust;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
@@ -71,17 +76,32 @@ pub enum Command {
     /// Remove features/profiles, or reset user definitions to the default.
     Remove(remove::Args),
 
+    /// View the revision history of a user profile/feature.
+    History(history::Args),
+
+    /// Roll back a user profile/feature to a prior revision.
+    Revert(revert::Args),
+
     /// Export user profiles.
     Export(export::Args),
 
     /// Import user profiles.
     Import(import::Args),
 
+    /// Snapshot and restore profile home directories.
+    Home(home::Args),
+
     /// Get information about profiles/features
     Info(info::Args),
 
     /// Package a profile into a self-contained executable.
     Package(package::Args),
+
+    /// Manage the configuration and cache stores.
+    Store(store::Args),
+
+    /// Approve the project-local overrides in the working directory.
+    Trust(trust::Args),
 }
 impl Default for Command {
     fn default() -> Self {
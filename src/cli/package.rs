@@ -3,34 +3,42 @@
 use crate::{
     cli::{run, run_vec},
     fab::{get_libraries, lib::ROOTS},
-    setup::setup,
+    setup::{Info, setup},
     shared::{
         Map,
+        delta::Delta,
         env::PWD,
         find::{DirType, recursive_crawl},
-        package::{PACKAGE_MARKER, Package},
-        profile::Profile,
-        store,
+        package::{self, Allowlist, Entry, Package},
+        profile::{Profile, seccomp::SeccompPolicy},
+        signature::{self, Trailer},
+        store, syscalls,
     },
 };
-use anyhow::Result;
-use bilrost::Message;
-use clap::ValueHint;
-use log::info;
+use anyhow::{Result, anyhow};
+use bstr::BString;
+use clap::{Subcommand, ValueHint};
+use dialoguer::console::style;
+use log::{info, warn};
+use path_clean::clean;
 use std::{
     borrow::Cow,
     env,
     fs::{self, File},
-    io::{Read, Seek, SeekFrom, Write},
+    io::{Read, Write},
     os::unix::fs::PermissionsExt,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(clap::Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// The name of the profile/feature to export. If absent, export all user-profiles/features.
-    #[arg(value_hint = ValueHint::CommandName)]
-    profile: String,
+    #[command(subcommand)]
+    command: Option<Action>,
+
+    /// The name of the profile to package.
+    #[arg(value_hint = ValueHint::CommandName, required = true)]
+    profile: Option<String>,
 
     /// Where to export to. Defaults to current directory
     #[arg(short, long, value_hint = ValueHint::DirPath)]
@@ -40,26 +48,85 @@ pub struct Args {
     #[arg(short, long)]
     version: Option<String>,
 
+    /// The zstd compression level of the payload.
+    #[arg(short, long, default_value_t = 19, value_parser = clap::value_parser!(i32).range(1..=22))]
+    level: i32,
+
+    /// Additional profiles to bundle into the package. They share its contents, and
+    /// are run by symlinking the package to the name of the profile, or with `--profile`.
+    #[arg(short, long, value_delimiter = ',', value_hint = ValueHint::CommandName)]
+    bundle: Vec<String>,
+
+    /// Also create a delta that updates the given package to this one.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    delta_from: Option<PathBuf>,
+
+    /// Sign the package with the PEM encoded Ed25519 private key at the path.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    sign: Option<PathBuf>,
+
     /// Run arguments
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
     pub passthrough: Option<Vec<String>>,
 }
+#[derive(Subcommand)]
+pub enum Action {
+    /// Print the contents of a package without running it.
+    Inspect {
+        /// The package to inspect.
+        #[arg(value_hint = ValueHint::FilePath)]
+        package: PathBuf,
+    },
+
+    /// Extract the contents of a package into a directory without running it.
+    Extract {
+        /// The package to extract.
+        #[arg(value_hint = ValueHint::FilePath)]
+        package: PathBuf,
+
+        /// The directory to extract into. It must not already exist.
+        #[arg(value_hint = ValueHint::DirPath)]
+        dest: PathBuf,
+
+        /// Extract the package even if it is unsigned, or signed by an untrusted key.
+        #[arg(long)]
+        insecure: bool,
+    },
+}
+
 impl super::Run for Args {
+    fn run(self) -> Result<()> {
+        match self.command {
+            Some(Action::Inspect { package }) => inspect(&package),
+            Some(Action::Extract {
+                package,
+                dest,
+                insecure,
+            }) => extract(&package, &dest, insecure),
+            None => self.build(),
+        }
+    }
+}
+impl Args {
+    /// Build a package from the profile.
     #[allow(clippy::unwrap_used)]
     #[allow(clippy::too_many_lines)]
-    fn run(self) -> Result<()> {
+    fn build(self) -> Result<()> {
+        let Some(profile_name) = self.profile else {
+            return Err(anyhow!("No profile to package"));
+        };
         store::CACHE.lock().replace(false);
-        let name = if let Some(version) = self.version {
-            format!("{}-{}.sb", self.profile, version)
+        let name = if let Some(version) = &self.version {
+            format!("{profile_name}-{version}.sb")
         } else {
-            format!("{}.sb", self.profile)
+            format!("{profile_name}.sb")
         };
 
         let dest = self
             .dest
             .map_or_else(|| PWD.clone(), PathBuf::from)
             .join(name);
-        let (profile, _) = Profile::new(&self.profile, None, None, false)?;
+        let (profile, _) = Profile::new(&profile_name, None, None, false)?;
         let exe_path = env::current_exe()?;
         let mut binary_file = File::open(&exe_path)?;
 
@@ -68,8 +135,10 @@ impl super::Run for Args {
 
         // 2. Prepare the data
         let mut package = Package {
-            name: self.profile.clone(),
+            name: profile_name.clone(),
             profile,
+            builder: env!("CARGO_PKG_VERSION").to_owned(),
+            version: self.version,
             ..Default::default()
         };
 
@@ -95,39 +164,77 @@ impl super::Run for Args {
             Ok(())
         };
 
-        let id = package
-            .profile
-            .id
-            .as_ref()
-            .map_or_else(|| self.profile.clone(), Clone::clone);
-        integration_bundle(&id, &mut package)?;
-        for (name, config) in package.profile.configuration.clone() {
-            let c_id = config
-                .id
-                .as_ref()
-                .map_or_else(|| self.profile.clone(), Clone::clone);
-            if config.id(&name) != id {
-                integration_bundle(&c_id, &mut package)?;
-            }
-        }
+        // Desktop files and icons for a profile, and each of its configurations.
+        let integrate_profile =
+            |name: &str, profile: &Profile, package: &mut Package| -> Result<()> {
+                let id = profile
+                    .id
+                    .as_ref()
+                    .map_or_else(|| name.to_owned(), Clone::clone);
+                integration_bundle(&id, package)?;
+                for (c_name, config) in &profile.configuration {
+                    let c_id = config
+                        .id
+                        .as_ref()
+                        .map_or_else(|| name.to_owned(), Clone::clone);
+                    if config.id(c_name) != id {
+                        integration_bundle(&c_id, package)?;
+                    }
+                }
+                Ok(())
+            };
+
+        let main = package.profile.clone();
+        integrate_profile(&profile_name, &main, &mut package)?;
 
         let mut args = self
             .passthrough
             .map_or_else(run::Args::default, |passthrough| {
-                run_vec(&self.profile, passthrough)
+                run_vec(&profile_name, passthrough)
             });
         args.dry = true;
         args.refresh = true;
 
-        let mut package = setup(
-            Cow::Owned(self.profile),
+        let info = setup(
+            Cow::Owned(profile_name),
             &mut args,
             false,
             Some((package, false)),
-        )?
-        .package
-        .unwrap()
-        .0;
+        )?;
+        let allowlist = export(&info);
+        let mut package = info.package.unwrap().0;
+        package.allowlist = allowlist;
+
+        // Bundled profiles are collected into the same package. Since contents are keyed
+        // by their destination, anything the profiles share is only stored once.
+        for name in self.bundle {
+            if name == package.name || package.bundled.contains_key(&name) {
+                continue;
+            }
+
+            info!("Bundling {name}");
+            let (mut profile, _) = Profile::new(&name, None, None, false)?;
+            integrate_profile(&name, &profile, &mut package)?;
+
+            let mut args = run::Args {
+                profile: name.clone(),
+                dry: true,
+                refresh: true,
+                ..Default::default()
+            };
+            let info = setup(
+                Cow::Borrowed(name.as_str()),
+                &mut args,
+                false,
+                Some((package, false)),
+            )?;
+            let allowlist = export(&info);
+            package = info.package.unwrap().0;
+
+            let roots = &mut profile.libraries.get_or_insert_default().roots;
+            roots.extend(ROOTS.iter().map(|r| String::from(r.as_ref())));
+            package.bundled.insert(name, Entry { profile, allowlist });
+        }
 
         let roots = &mut package.profile.libraries.get_or_insert_default().roots;
         roots.extend(ROOTS.iter().map(|r| String::from(r.as_ref())));
@@ -155,28 +262,208 @@ impl super::Run for Args {
         package.system_libraries = depend;
 
         info!("Packing...");
-        let bytes = zstd::encode_all(Package::encode_to_vec(&package).as_slice(), 3)?;
+        let bytes = package::encode(&package, self.level)?;
 
+        // Write the original binary, marked as a package, followed by the payload.
+        let content = package::assemble(&binary_content, &bytes);
         let mut out_file = File::create(&dest)?;
+        out_file.write_all(&content)?;
 
-        // Write original binary
-        out_file.write_all(&binary_content)?;
-
-        // Write Header: Marker + Length
-        out_file.write_all(&PACKAGE_MARKER)?;
-
-        // Write Payload
-        out_file.write_all(&bytes)?;
-
-        out_file.seek(SeekFrom::Start(9))?;
-        out_file.write_all(&PACKAGE_MARKER)?;
+        // Write the Signature Trailer, which covers everything before it.
+        if let Some(key) = &self.sign {
+            let trailer = signature::sign(&content, key)?;
+            info!("Signed with key {}", trailer.fingerprint());
+            out_file.write_all(&trailer.to_bytes())?;
+        }
 
         out_file.sync_all()?;
         let metadata = fs::metadata(&dest)?;
         let mut perms = metadata.permissions();
         perms.set_mode(0o755);
-        fs::set_permissions(dest, perms)?;
+        fs::set_permissions(&dest, perms)?;
+
+        if let Some(old) = &self.delta_from {
+            let (base, _) = open(old)?;
+            let delta = dest.with_extension("delta");
+            Delta::new(&base, package).write(&delta, self.level, self.sign.as_deref())?;
+            println!("Created delta {}", delta.display());
+        }
+        Ok(())
+    }
+}
+
+/// Export the SECCOMP policy of a profile from our database, so that the package
+/// keeps it on other machines.
+fn export(info: &Info) -> Allowlist {
+    if !matches!(
+        info.profile.seccomp.unwrap_or_default(),
+        SeccompPolicy::Enforcing | SeccompPolicy::Notifying
+    ) {
+        return Allowlist::default();
+    }
+
+    let (calls, bwrap) = syscalls::get_calls(&info.name, &info.profile.binaries);
+    let mut allowlist = Allowlist {
+        syscalls: syscalls::get_names(calls),
+        bwrap: syscalls::get_names(bwrap),
+    };
+    allowlist.syscalls.sort_unstable();
+    allowlist.bwrap.sort_unstable();
+
+    if allowlist.is_empty() {
+        warn!(
+            "No syscalls are recorded for {}. The package will run without SECCOMP",
+            info.name
+        );
+    } else {
+        info!(
+            "Embedding {} syscalls for {}",
+            allowlist.syscalls.len(),
+            info.name
+        );
+    }
+    allowlist
+}
+
+/// Format a size in bytes for humans.
+#[allow(clippy::cast_precision_loss)]
+fn size(bytes: usize) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return format!("{size:.1} {unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1} TiB")
+}
+
+/// Read a package from a file, alongside the status of its signature.
+fn open(path: &Path) -> Result<(Package, String)> {
+    let bytes = fs::read(path)?;
+    let trailer = Trailer::parse(&bytes);
+    let status = match &trailer {
+        None => style("Unsigned").yellow().to_string(),
+        Some(trailer) => {
+            let signed = package::signed(&bytes, trailer).unwrap_or_default();
+            match signature::verify(signed, Some(trailer), false) {
+                Ok(()) => style(format!("Trusted ({})", trailer.fingerprint()))
+                    .green()
+                    .to_string(),
+                Err(signature::Error::Unknown(fingerprint)) => {
+                    style(format!("Unknown signer ({fingerprint})"))
+                        .yellow()
+                        .to_string()
+                }
+                Err(e) => style(format!("{e}")).red().bold().to_string(),
+            }
+        }
+    };
+
+    let package = package::extract(&bytes, trailer.as_ref())
+        .ok_or_else(|| anyhow!("{} is not a valid package", path.display()))?;
+    Ok((package, status))
+}
 
+/// Print the contents of a package.
+fn inspect(path: &Path) -> Result<()> {
+    let (package, status) = open(path)?;
+
+    let title = package.version.as_ref().map_or_else(
+        || package.name.clone(),
+        |version| format!("{} ({version})", package.name),
+    );
+    println!("\n{}", style(format!("=== {title} ===")).bold());
+    println!("Built by Antimony {}", style(&package.builder).italic());
+    println!("Signature: {status}");
+
+    let show = |title: &str, profile: &Profile, allowlist: &Allowlist| -> Result<()> {
+        println!("\n{}", style(format!("=== {title} ===")).bold());
+        print!("{}", toml::to_string(profile)?);
+
+        if !allowlist.is_empty() {
+            println!(
+                "\n{}",
+                style(format!(
+                    "=== SECCOMP Allowlist ({}) ===",
+                    allowlist.syscalls.len()
+                ))
+                .bold()
+            );
+            println!("\t{}", allowlist.syscalls.join(" "));
+        }
         Ok(())
+    };
+
+    show("Profile", &package.profile, &package.allowlist)?;
+    let mut bundled: Vec<_> = package.bundled.iter().collect();
+    bundled.sort_by_key(|(name, _)| *name);
+    for (name, entry) in bundled {
+        show(
+            &format!("Bundled: {name}"),
+            &entry.profile,
+            &entry.allowlist,
+        )?;
+    }
+
+    let print = |title: &str, entries: &Map<String, BString>| {
+        if entries.is_empty() {
+            return;
+        }
+        let total: usize = entries.values().map(|content| content.len()).sum();
+        println!(
+            "\n{}",
+            style(format!(
+                "=== {title} ({}, {}) ===",
+                entries.len(),
+                size(total)
+            ))
+            .bold()
+        );
+
+        let mut entries: Vec<_> = entries.iter().collect();
+        entries.sort_by_key(|(path, _)| *path);
+        for (path, content) in entries {
+            println!("\t{} {}", style(size(content.len())).dim(), path);
+        }
+    };
+
+    print("Files", &package.files);
+    print("Libraries", &package.libraries);
+    print("Binaries", &package.binaries);
+    print("System Binaries", &package.system_binaries);
+    print("System Libraries", &package.system_libraries);
+    print("System Files", &package.system_files);
+
+    if !package.symlinks.is_empty() {
+        println!(
+            "\n{}",
+            style(format!("=== Symlinks ({}) ===", package.symlinks.len())).bold()
+        );
+        let mut symlinks: Vec<_> = package.symlinks.iter().collect();
+        symlinks.sort();
+        for (src, dest) in symlinks {
+            println!("\t{src} -> {dest}");
+        }
     }
+    Ok(())
+}
+
+/// Extract the contents of a package into a directory.
+fn extract(path: &Path, dest: &Path, insecure: bool) -> Result<()> {
+    let dest = clean(PWD.join(dest));
+    if dest.exists() {
+        return Err(anyhow!("{} already exists", dest.display()));
+    }
+
+    // Extracting is held to the same trust as running the package.
+    let bytes = fs::read(path)?;
+    let trailer = Trailer::parse(&bytes);
+    package::verify(&bytes, trailer.as_ref(), insecure)?;
+
+    let package = package::extract(&bytes, trailer.as_ref())
+        .ok_or_else(|| anyhow!("{} is not a valid package", path.display()))?;
+    package.unpack(&dest)?;
+    println!("Extracted {} to {}", package.name, dest.display());
+    Ok(())
 }
@@ -0,0 +1,55 @@
+//! Roll back a user profile/feature to a prior revision.
+
+use crate::{
+    cli,
+    shared::store::{
+        Object, USER_STORE,
+        history::{self, RevertError},
+    },
+};
+use anyhow::{Result, anyhow};
+use clap::ValueHint;
+use dialoguer::console::style;
+
+#[derive(clap::Args, Default)]
+pub struct Args {
+    /// The name of the profile/feature.
+    #[arg(value_hint = ValueHint::CommandName)]
+    pub name: String,
+
+    /// The revision to revert to. Defaults to the revision before the latest.
+    pub rev: Option<usize>,
+
+    /// Target the feature set rather than the profile set.
+    #[arg(short, long)]
+    pub feature: bool,
+}
+impl cli::Run for Args {
+    fn run(self) -> Result<()> {
+        let (table, kind) = if self.feature {
+            (Object::Feature, "feature")
+        } else {
+            (Object::Profile, "profile")
+        };
+
+        match history::revert(USER_STORE.borrow(), &self.name, table, self.rev) {
+            Ok(Some(rev)) => println!("Reverted {} to revision {rev}", self.name),
+            Ok(None) => println!("Removed user {kind}: {}", self.name),
+            Err(RevertError::NoHistory) => {
+                return Err(anyhow!("No history for {kind}: {}", self.name));
+            }
+            Err(RevertError::Current(rev)) => {
+                return Err(anyhow!("{} is already at revision {rev}", self.name));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if self.name == "default" || self.feature {
+            eprintln!(
+                "{}",
+                style("Note: Profiles will not use your changes until you refresh them.").yellow()
+            );
+        }
+        Ok(())
+    }
+}
@@ -9,8 +9,8 @@ use crate::{
         env::{RUNTIME_DIR, SESSION_BUS},
         profile::{
             files::FileMode,
-            home::{HomeLockPolicy, HomePolicy},
-            ipc::Portal,
+            home::{HomeLockPolicy, HomePolicy, parse_account},
+            ipc::{Portal, Rule},
             ns::Namespace,
             seccomp::SeccompPolicy,
         },
@@ -46,6 +46,11 @@ pub struct Args {
     #[arg(long)]
     pub dry: bool,
 
+    /// Keep the proxies of a dry run, rather than discard them, so that their arguments
+    /// can be read. Used by `antimony export --script`.
+    #[arg(skip)]
+    pub record: bool,
+
     /// Refresh cache definitions. Analogous to `antimony refresh`
     #[arg(long)]
     pub refresh: bool,
@@ -100,6 +105,10 @@ pub struct Args {
     #[arg(long)]
     pub home_lock_policy: Option<HomeLockPolicy>,
 
+    /// Use a separate account of the home
+    #[arg(long, value_parser = parse_account)]
+    pub account: Option<String>,
+
     /// Override the seccomp policy
     #[arg(long)]
     pub seccomp: Option<SeccompPolicy>,
@@ -120,9 +129,25 @@ pub struct Args {
     #[arg(long, value_delimiter = ' ', num_args = 1..)]
     pub owns: Option<Vec<String>>,
 
-    /// Add busses the sandbox can call.
+    /// Add methods the sandbox can call, as NAME=INTERFACE.METHOD@PATH.
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    pub calls: Option<Vec<Rule>>,
+
+    /// Add signals the sandbox can receive, as NAME=INTERFACE.SIGNAL@PATH.
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    pub broadcasts: Option<Vec<Rule>>,
+
+    /// Add system busses the sandbox can see.
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    pub system_sees: Option<Vec<String>>,
+
+    /// Add system busses the sandbox can talk to.
+    #[arg(long, value_delimiter = ' ', num_args = 1..)]
+    pub system_talks: Option<Vec<String>>,
+
+    /// Add methods the sandbox can call on the system bus, as NAME=INTERFACE.METHOD@PATH.
     #[arg(long, value_delimiter = ' ', num_args = 1..)]
-    pub calls: Option<Vec<String>>,
+    pub system_calls: Option<Vec<Rule>>,
 
     /// Disable all IPC. This overrules all other IPC settings.
     #[arg(long)]
@@ -224,6 +249,7 @@ impl cli::Run for Args {
                     return Err(anyhow!(fail));
                 }
             }
+            Err(e) if e.is::<setup::Focused>() => log::info!("{e}"),
             Err(e) => {
                 let fail = format!("Failed to run {}: {e}", self.profile);
                 error!("{fail}");
@@ -247,6 +273,7 @@ impl Args {
                     return Err(anyhow!(fail));
                 }
             }
+            Err(e) if e.is::<setup::Focused>() => log::info!("{e}"),
             Err(e) => {
                 let fail = format!("Failed to run {}: {e}", self.profile);
                 error!("{fail}");
@@ -271,16 +298,11 @@ pub fn wait_for_doc() {
     }
 }
 
-/// Run the profile.
+/// Append the application, or the sandbox arguments that replace it, to the command.
 ///
 /// ## Errors
-/// If the profile cannot be run
-#[allow(
-    clippy::missing_panics_doc,
-    reason = "This function never actually panics"
-)]
-#[allow(clippy::too_many_lines)]
-pub fn run(mut info: setup::Info, args: &mut Args) -> Result<()> {
+/// If the sandbox arguments of features conflict.
+pub fn command(info: &mut setup::Info) -> Result<()> {
     let sandbox_args = &info.profile.sandbox_args;
     let add_regular = if sandbox_args.is_empty() {
         true
@@ -328,8 +350,22 @@ pub fn run(mut info: setup::Info, args: &mut Args) -> Result<()> {
         } else {
             info.handle.arg_i(localize_home(&app_path));
         }
-        info.handle.args_i(info.post);
+        info.handle.args_i(info.post.drain(..));
     }
+    Ok(())
+}
+
+/// Run the profile.
+///
+/// ## Errors
+/// If the profile cannot be run
+#[allow(
+    clippy::missing_panics_doc,
+    reason = "This function never actually panics"
+)]
+#[allow(clippy::too_many_lines)]
+pub fn run(mut info: setup::Info, args: &mut Args) -> Result<()> {
+    command(&mut info)?;
 
     // Run it
     if args.dry {
@@ -388,6 +424,9 @@ pub fn run(mut info: setup::Info, args: &mut Args) -> Result<()> {
         }
 
         let mut handle = info.handle.spawn()?;
+        if let Some(home) = &info.lock {
+            setup::record_lock(home, info.instance.name());
+        }
         if !info.package.map_or_else(|| false, |(_, b)| b) {
             mem::flush();
         }
@@ -473,6 +512,12 @@ pub fn run(mut info: setup::Info, args: &mut Args) -> Result<()> {
                 )?;
             }
         }
+
+        if let Some(vault) = info.vault.take()
+            && let Err(e) = vault.close()
+        {
+            log::warn!("Failed to close encrypted home: {e}");
+        }
         ret
     }
 }
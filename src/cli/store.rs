@@ -0,0 +1,101 @@
+//! Manage the backing stores.
+
+use crate::{
+    cli,
+    shared::{
+        config::CONFIG_FILE,
+        privileged,
+        store::{self, Backend, CACHE_STORE, Store, StoreType, compress},
+    },
+};
+use anyhow::Result;
+use clap::Subcommand;
+use dialoguer::console::style;
+use log::warn;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Action,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Copy all profiles, features and caches from one backend to another.
+    Migrate {
+        /// The backend to migrate to.
+        #[arg(long)]
+        to: Backend,
+
+        /// The backend to migrate from. Defaults to the configured backend.
+        #[arg(long)]
+        from: Option<Backend>,
+
+        /// Also migrate the system store. Requires privilege.
+        #[arg(short, long)]
+        system: bool,
+    },
+
+    /// Train a compression dictionary from the existing cache.
+    ///
+    /// Existing records are left as they are. Those compressed with a previous dictionary
+    /// can no longer be read, and are regenerated when next used; run `antimony refresh`
+    /// to regenerate them all at once.
+    Train,
+}
+
+impl cli::Run for Args {
+    fn run(self) -> Result<()> {
+        match self.command {
+            Action::Migrate { to, from, system } => {
+                let from = from.unwrap_or_else(|| CONFIG_FILE.store());
+                if from == to {
+                    return Err(anyhow::anyhow!("Source and destination are both {to}"));
+                }
+
+                let mut stores = vec![StoreType::User, StoreType::Cache];
+                if system {
+                    if privileged()? {
+                        stores.push(StoreType::System);
+                    } else {
+                        return Err(anyhow::anyhow!("Not allowed to modify system store"));
+                    }
+                }
+
+                for t in stores {
+                    let kind = match t {
+                        StoreType::System => "system",
+                        StoreType::User => "user",
+                        StoreType::Cache => "cache",
+                    };
+                    let src = Store::with(t, from)?;
+                    let dest = Store::with(t, to)?;
+                    match store::migrate(src.borrow(), dest.borrow()) {
+                        Ok(count) => println!("Migrated {count} {kind} objects from {from} to {to}"),
+                        Err(e) => warn!("Failed to migrate {kind} store: {e}"),
+                    }
+                }
+
+                if CONFIG_FILE.store() != to {
+                    eprintln!(
+                        "{}",
+                        style(format!(
+                            "Note: Set `store = \"{to}\"` in /etc/antimony.toml to use the new backend."
+                        ))
+                        .yellow()
+                    );
+                }
+                Ok(())
+            }
+
+            Action::Train => {
+                let size = compress::train(CACHE_STORE.borrow())?;
+                println!(
+                    "Trained a {size} byte dictionary at {}",
+                    compress::DICT_PATH.display()
+                );
+                Ok(())
+            }
+        }
+    }
+}
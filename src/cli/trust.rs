@@ -0,0 +1,71 @@
+//! Approve the project-local overrides in the working directory.
+
+use crate::{
+    cli,
+    shared::{
+        profile::Profile,
+        project::{self, FRAGMENT},
+    },
+};
+use anyhow::{Result, anyhow};
+use dialoguer::{Confirm, console::style};
+use std::fs;
+
+#[derive(clap::Args, Default)]
+pub struct Args {
+    /// Revoke the approval, such that the overrides are ignored.
+    #[arg(long)]
+    pub revoke: bool,
+
+    /// Approve without asking.
+    #[arg(short, long, conflicts_with = "revoke")]
+    pub yes: bool,
+}
+impl cli::Run for Args {
+    fn run(self) -> Result<()> {
+        let fragment = project::find()
+            .ok_or_else(|| anyhow!("No {FRAGMENT} in this directory or its parents"))?;
+
+        if self.revoke {
+            if project::revoke(&fragment)? {
+                println!("Revoked {}", fragment.display());
+            } else {
+                println!("{} was not approved", fragment.display());
+            }
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&fragment)?;
+        if project::approved(&fragment, &contents) {
+            println!("{} is already approved", fragment.display());
+            return Ok(());
+        }
+
+        // Refuse anything that wouldn't load, rather than approve it.
+        toml::from_str::<Profile>(&contents)?;
+
+        if !self.yes {
+            println!(
+                "{}",
+                style(format!("=== {} ===", fragment.display())).bold()
+            );
+            println!("{contents}");
+            eprintln!(
+                "{}",
+                style("These overrides apply to every profile run beneath this directory.")
+                    .yellow()
+            );
+            if !Confirm::new()
+                .with_prompt("Approve?")
+                .default(false)
+                .interact()?
+            {
+                return Ok(());
+            }
+        }
+
+        project::approve(&fragment, &contents)?;
+        println!("Approved {}", fragment.display());
+        Ok(())
+    }
+}
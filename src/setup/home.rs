@@ -1,18 +1,33 @@
 #![allow(clippy::missing_docs_in_private_items)]
 
 use crate::shared::{
-    env::SESSION_BUS,
-    profile::home::{HomeLockPolicy, HomePolicy},
+    env::{HOME, SESSION_BUS},
+    profile::home::{Home, HomeLockPolicy, HomePolicy},
+    snapshot::{self, AUTO_LABEL, RETENTION},
+    store::history,
     utility,
+    vault::{VAULT_DIR, Vault},
 };
 use anyhow::{Result, anyhow};
+use dbus::{arg::PropMap, blocking::LocalConnection};
 use heck::ToTitleCase;
 use inotify::{Inotify, WatchMask};
-use nix::sys::signal::Signal::SIGKILL;
+use log::warn;
+use nix::{
+    sys::signal::{
+        Signal::{SIGKILL, SIGTERM},
+        kill,
+    },
+    unistd::{Pid, getpid},
+};
+use serde::{Deserialize, Serialize};
 use spawn::{Spawner, StreamMode};
 use std::{
     fs::{self, File, TryLockError},
     io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    thread,
+    time::Duration,
 };
 
 #[allow(clippy::too_many_lines)]
@@ -26,6 +41,17 @@ pub fn setup(args: &mut super::Args) -> Result<Option<String>> {
         && policy != HomePolicy::None
     {
         let home_dir = home.path(&args.name);
+        if let Some(account) = &home.account
+            && !home_dir.exists()
+            && !args.run.dry
+        {
+            return Err(anyhow!(
+                "{} has no account {account}. Create it with `antimony home accounts {} --create {account}`",
+                args.name,
+                args.name
+            ));
+        }
+
         if home.lock.unwrap_or(false)
             && !args.run.dry
             && home_dir.exists()
@@ -39,88 +65,113 @@ pub fn setup(args: &mut super::Args) -> Result<Option<String>> {
             ));
 
             match lock {
-                Ok(()) => args.handle.fd_i(file),
+                Ok(()) => {
+                    args.handle.fd_i(file);
+                    args.lock = Some(home_dir.clone());
+                }
                 Err(TryLockError::WouldBlock) => {
-                    {
-                        match home.lock_policy.unwrap_or_default() {
-                            HomeLockPolicy::Notify => {
-                                // Attach a notify watch on the directory to see if the running instance closes it.
-                                let mut inotify = Inotify::init()?;
-                                let wd = inotify.watches().add(&home_dir, WatchMask::CLOSE)?;
-                                let mut buffer = [0; 1024];
-                                let title = args.name.to_title_case();
-                                let mut prompt = Spawner::abs(utility("notify"))
-                            .env("DBUS_SESSION_BUS_ADDRESS", SESSION_BUS.as_str())
-                            .mode(user::Mode::Real)
-                            .output(StreamMode::Pipe)
-                            .args([
-                                "--title",
-                                &format!("{title} is Locked"),
-                                "--body",
-                                &format!(
+                    // The lock is released when its holder exits, so the record only
+                    // describes the holder; it is only trusted while that is still running.
+                    let owner = Owner::read(&home_dir).filter(Owner::alive);
+
+                    match home.lock_policy.unwrap_or_default() {
+                        HomeLockPolicy::Notify => {
+                            // Attach a notify watch on the directory to see if the running instance closes it.
+                            let mut inotify = Inotify::init()?;
+                            let wd = inotify.watches().add(&home_dir, WatchMask::CLOSE)?;
+                            let mut buffer = [0; 1024];
+                            let title = args.name.to_title_case();
+
+                            let body = owner.as_ref().map_or_else(
+                                || format!(
                                     "{title}'s home folder has been locked by another instance. If you can confirm no such \
                                     instance exists, it's possible it was terminated before the lock could be removed. However, \
                                     bypassing the lock when another instance is running may cause issues. You can also skip mounting \
                                     the home folder for this instance, or mount it on an overlay."
                                 ),
-                                "--timeout",
-                                "10000",
-                                "--action", "Ignore",
-                                "--action", "Unlock",
-                                "--action", "Skip",
-                                "--action", "Overlay",
-                                "--action", "Abort"
-                            ])
-                            .spawn()?;
-
-                                while prompt.alive()?.is_some() {
-                                    match inotify.read_events(&mut buffer) {
-                                        Ok(events) => {
-                                            for event in events {
-                                                if event.wd == wd {
-                                                    break;
-                                                }
+                                |owner| format!(
+                                    "{title}'s home folder is in use by instance {} (PID {}), started {}. You can focus \
+                                    or stop that instance, skip mounting the home folder for this instance, or mount it on an overlay.",
+                                    owner.instance, owner.pid, history::age(owner.time)
+                                ),
+                            );
+
+                            let mut actions = vec!["Ignore", "Unlock"];
+                            if owner.is_some() {
+                                actions.extend(["Focus", "Stop"]);
+                            }
+                            actions.extend(["Skip", "Overlay", "Abort"]);
+
+                            let mut prompt = Spawner::abs(utility("notify"))
+                                .env("DBUS_SESSION_BUS_ADDRESS", SESSION_BUS.as_str())
+                                .mode(user::Mode::Real)
+                                .output(StreamMode::Pipe)
+                                .args([
+                                    "--title",
+                                    &format!("{title} is Locked"),
+                                    "--body",
+                                    &body,
+                                    "--timeout",
+                                    "10000",
+                                ])
+                                .args(actions.into_iter().flat_map(|action| ["--action", action]))
+                                .spawn()?;
+
+                            while prompt.alive()?.is_some() {
+                                match inotify.read_events(&mut buffer) {
+                                    Ok(events) => {
+                                        for event in events {
+                                            if event.wd == wd {
+                                                break;
                                             }
                                         }
-                                        Err(error) if error.kind() == ErrorKind::WouldBlock => {
-                                            continue;
-                                        }
-                                        _ => return Err(anyhow!("Error while reading events")),
                                     }
+                                    Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                                        continue;
+                                    }
+                                    _ => return Err(anyhow!("Error while reading events")),
                                 }
+                            }
 
-                                inotify.watches().remove(wd)?;
+                            inotify.watches().remove(wd)?;
 
-                                if prompt.alive()?.is_none() {
-                                    let choice = prompt.output_all()?;
-                                    match choice.as_str() {
-                                        "Ignore\n" => cont = true,
-                                        "Unlock\n" => {
-                                            File::open(&home_dir)?.unlock()?;
-                                            cont = true;
-                                        }
-                                        "Skip\n" => return Ok(None),
-                                        "Overlay\n" => {
-                                            policy = HomePolicy::Overlay;
-                                            cont = true;
-                                        }
-                                        "Abort\n" => return error,
-                                        _ => {}
+                            if prompt.alive()?.is_none() {
+                                let choice = prompt.output_all()?;
+                                match (choice.as_str(), &owner) {
+                                    ("Ignore\n", _) => cont = true,
+                                    ("Unlock\n", _) => {
+                                        File::open(&home_dir)?.unlock()?;
+                                        cont = true;
+                                    }
+                                    ("Focus\n", Some(_)) => {
+                                        focus(&args.id)?;
+                                        return Err(super::Focused(title).into());
                                     }
-                                } else {
-                                    prompt.signal(SIGKILL)?;
+                                    ("Stop\n", Some(owner)) => owner.stop()?,
+                                    ("Skip\n", _) => return Ok(None),
+                                    ("Overlay\n", _) => {
+                                        policy = HomePolicy::Overlay;
+                                        cont = true;
+                                    }
+                                    ("Abort\n", _) => return error,
+                                    _ => {}
                                 }
+                            } else {
+                                prompt.signal(SIGKILL)?;
                             }
-                            HomeLockPolicy::Abort => return error,
-                            HomeLockPolicy::Overlay => {
-                                policy = HomePolicy::Overlay;
-                                cont = true;
-                            }
+                        }
+                        HomeLockPolicy::Abort => return error,
+                        HomeLockPolicy::Overlay => {
+                            policy = HomePolicy::Overlay;
+                            cont = true;
                         }
                     }
 
                     match file.try_lock() {
-                        Ok(()) => args.handle.fd_i(file),
+                        Ok(()) => {
+                            args.handle.fd_i(file);
+                            args.lock = Some(home_dir.clone());
+                        }
                         Err(_) => {
                             if !cont {
                                 return error;
@@ -132,6 +183,36 @@ pub fn setup(args: &mut super::Args) -> Result<Option<String>> {
             }
         }
 
+        // Snapshots only make sense when the sandbox can actually change the home.
+        if policy == HomePolicy::Enabled
+            && home.snapshot_on_launch.unwrap_or(false)
+            && !args.run.dry
+            && home_dir.exists()
+        {
+            let dir = home.snapshots(&args.name);
+            let keep = home.snapshot_retention.unwrap_or(RETENTION);
+            if let Err(e) = snapshot::take(&home_dir, &dir, Some(AUTO_LABEL))
+                .and_then(|_| snapshot::prune(&dir, usize::try_from(keep).unwrap_or(usize::MAX)))
+            {
+                warn!("Failed to snapshot home: {e}");
+            }
+        }
+
+        // An encrypted home is locked by its cipher directory, but what we mount is the vault.
+        let home_dir = if home.policy == Some(HomePolicy::Encrypted) {
+            let name = home.key(&args.name);
+            if args.run.dry {
+                VAULT_DIR.join(name)
+            } else {
+                let vault = Vault::open(&home_dir, &name, home.secret.unwrap_or_default())?;
+                let mount = vault.mount.clone();
+                args.vault = Some(vault);
+                mount
+            }
+        } else {
+            home_dir
+        };
+
         let home_str = home_dir.to_string_lossy();
         if !home_dir.exists() && !args.run.dry {
             fs::create_dir_all(&home_dir)?;
@@ -140,7 +221,12 @@ pub fn setup(args: &mut super::Args) -> Result<Option<String>> {
         let dest = home.path.as_ref().map_or("/home/antimony", |path| path);
 
         match policy {
-            HomePolicy::Enabled => {
+            HomePolicy::Enabled | HomePolicy::Encrypted
+                if !home.persist.is_empty() || home.seed.is_some() =>
+            {
+                persist(&args.handle, home, &home_dir, dest, args.run.dry)?;
+            }
+            HomePolicy::Enabled | HomePolicy::Encrypted => {
                 args.handle.args_i(["--bind", &home_str, dest]);
             }
             _ => {
@@ -169,3 +255,174 @@ pub fn setup(args: &mut super::Args) -> Result<Option<String>> {
         Ok(None)
     }
 }
+
+/// Mount a fresh home, optionally seeded from a template, and bind only the persisted
+/// paths from the home folder into it.
+fn persist(handle: &Spawner, home: &Home, home_dir: &Path, dest: &str, dry: bool) -> Result<()> {
+    if let Some(seed) = &home.seed {
+        let seed = if seed == "~" || seed.starts_with("~/") {
+            seed.replacen('~', HOME.as_str(), 1)
+        } else {
+            seed.clone()
+        };
+        if !Path::new(&seed).is_dir() {
+            return Err(anyhow!("Home seed {seed} is not a directory"));
+        }
+        handle.args_i(["--overlay-src", &seed, "--tmp-overlay", dest]);
+    } else {
+        handle.args_i(["--tmpfs", dest]);
+    }
+
+    // Sorted, so parents are mounted before their children.
+    let mut paths: Vec<_> = home.persist.iter().collect();
+    paths.sort();
+    for path in paths {
+        let relative = Path::new(path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow!(
+                "Persisted paths must be relative to the home: {path}"
+            ));
+        }
+
+        // Paths that don't exist yet are assumed to be directories.
+        let src = home_dir.join(relative);
+        if !src.exists() && !dry {
+            fs::create_dir_all(&src)?;
+        }
+        handle.args_i([
+            "--bind",
+            &src.to_string_lossy(),
+            &format!("{dest}/{}", relative.display()),
+        ]);
+    }
+    Ok(())
+}
+
+/// The instance holding the lock of a home, recorded beside it so that
+/// other instances can describe it.
+#[derive(Deserialize, Serialize)]
+struct Owner {
+    /// The PID of the sandbox holding the lock.
+    pid: i32,
+
+    /// The name of the instance.
+    instance: String,
+
+    /// The boot the instance was started in.
+    boot: String,
+
+    /// When the process started, in clock ticks since boot. This tells a
+    /// reused PID apart from the original.
+    start: u64,
+
+    /// When the instance was started, in seconds since the epoch.
+    time: u64,
+}
+impl Owner {
+    /// Describe the process holding the lock.
+    fn new(pid: i32, instance: &str) -> Self {
+        Self {
+            pid,
+            instance: instance.to_owned(),
+            boot: boot_id(),
+            start: start_time(pid).unwrap_or_default(),
+            time: history::now(),
+        }
+    }
+
+    /// Where the owner of a home is recorded.
+    fn path(home: &Path) -> PathBuf {
+        let name = home
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        home.with_file_name(format!(".{name}.lock"))
+    }
+
+    /// Read the owner of a home, if recorded.
+    fn read(home: &Path) -> Option<Self> {
+        toml::from_str(&fs::read_to_string(Self::path(home)).ok()?).ok()
+    }
+
+    /// Record the owner of a home.
+    fn write(&self, home: &Path) -> Result<()> {
+        fs::write(Self::path(home), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Whether the owner is still running, in this boot.
+    fn alive(&self) -> bool {
+        self.boot == boot_id() && start_time(self.pid) == Some(self.start)
+    }
+
+    /// Stop the owner, waiting for it to exit.
+    fn stop(&self) -> Result<()> {
+        kill(Pid::from_raw(self.pid), SIGTERM)?;
+        for _ in 0..50 {
+            if !self.alive() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Err(anyhow!("Instance {} did not stop", self.instance))
+    }
+}
+
+/// Record the sandbox holding the lock of a home, once it has been spawned.
+///
+/// The lock is held by whatever has the file descriptor passed to the sandbox,
+/// so the holder is the oldest process, other than Antimony, with the home open.
+pub fn record(home: &Path, instance: &str) {
+    let own = getpid().as_raw();
+    let holder = fs::read_dir("/proc")
+        .into_iter()
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            fs::read_dir(format!("/proc/{pid}/fd")).is_ok_and(|fds| {
+                fds.filter_map(Result::ok)
+                    .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link == home))
+            })
+        })
+        .filter_map(|pid| Some((start_time(pid)?, pid)))
+        .min();
+
+    match holder {
+        Some((_, pid)) => {
+            if let Err(e) = Owner::new(pid, instance).write(home) {
+                warn!("Failed to record the owner of the home lock: {e}");
+            }
+        }
+        None => warn!("Failed to find the sandbox holding the home lock"),
+    }
+}
+
+/// The ID of the current boot.
+fn boot_id() -> String {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_owned())
+        .unwrap_or_default()
+}
+
+/// When a process started, in clock ticks since boot.
+fn start_time(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+
+    // The command name can contain anything, so skip past it. The start time is
+    // the 22nd field, the 20th after the name.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Ask a running instance to present itself, if it implements `org.freedesktop.Application`.
+fn focus(id: &str) -> Result<()> {
+    let connection = LocalConnection::new_session()?;
+    let path = format!("/{}", id.replace(['.', '-'], "/"));
+    let proxy = connection.with_proxy(id, path, Duration::from_secs(1));
+    let (): () = proxy.method_call("org.freedesktop.Application", "Activate", (PropMap::new(),))?;
+    Ok(())
+}
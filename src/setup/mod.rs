@@ -16,10 +16,11 @@ use crate::{
         Set,
         env::{CACHE_DIR, RUNTIME_DIR, RUNTIME_STR},
         find::{DirType, recursive_crawl},
-        package::{Package, get_profile},
+        package::{Allowlist, Package, get_profile},
         profile::{Profile, seccomp::SeccompPolicy},
         store::mem,
         utility,
+        vault::Vault,
     },
     timer,
 };
@@ -56,6 +57,15 @@ struct Args<'a> {
     /// If the boolean is false, we are *creating* the package. If true, we are *using* the package.
     pub package: Option<(Package, bool)>,
     pub run: &'a mut super::cli::run::Args,
+
+    /// The encrypted home, if mounted.
+    pub vault: Option<Vault>,
+
+    /// The home whose lock is passed to the sandbox.
+    pub lock: Option<PathBuf>,
+
+    /// The proxies, recorded rather than run.
+    pub proxies: Vec<Spawner>,
 }
 
 /// The information passed back to `run`.
@@ -68,6 +78,20 @@ pub struct Info {
     pub home: Option<String>,
     pub sys_dir: PathBuf,
     pub package: Option<(Package, bool)>,
+    pub vault: Option<Vault>,
+    pub lock: Option<PathBuf>,
+    pub proxies: Vec<Spawner>,
+}
+
+/// Setup stopped early, as the user chose to focus the running instance of the profile
+/// rather than launch another. This isn't a failure.
+#[derive(Debug, thiserror::Error)]
+#[error("Focused the running instance of {0}")]
+pub struct Focused(pub String);
+
+/// Record the spawned sandbox as the owner of the lock of `home`.
+pub fn record_lock(home: &Path, instance: &str) {
+    home::record(home, instance);
 }
 
 /// The main function within antimony. It takes a name, and spits out a sandbox ready to run.
@@ -78,9 +102,12 @@ pub fn setup<'a>(
     flush_defer: bool,
     mut package: Option<(Package, bool)>,
 ) -> Result<Info> {
-    let (mut profile, hash, profile_args) = if let Some((_, true)) = package {
+    let (mut profile, hash, profile_args) = if let Some((entry, true)) = &package {
         let path = PathBuf::from(name.into_owned());
-        let (mut profile, profile_path) = get_profile(&path)?;
+
+        // The name of the package holds the requested profile of a bundle, if any.
+        let entry = Some(entry.name.as_str()).filter(|entry| !entry.is_empty());
+        let (mut profile, profile_path) = get_profile(&path, entry)?;
         name = Cow::Owned(profile_path.file_stem().map_or_else(
             || profile_path.to_string_lossy().into_owned(),
             |stem| stem.to_string_lossy().into_owned(),
@@ -88,10 +115,6 @@ pub fn setup<'a>(
 
         let hash = profile.hash_str(&None);
 
-        // These do not work without a system installation.
-        profile.seccomp = Some(SeccompPolicy::Disabled);
-        profile.lockdown = Some(false);
-
         let mut profile_args = Vec::new();
         let root = path.join("root");
         if root.exists() {
@@ -185,7 +208,36 @@ pub fn setup<'a>(
         }
 
         profile = profile.base(Profile::from_args(args)?)?;
-        package = Some((Package::default(), true));
+
+        // Lockdown hands off to a SetUID utility, which cannot run within the package namespace.
+        if profile.lockdown.unwrap_or(false) {
+            warn!("Lockdown requires a system installation, and is disabled for packages");
+        }
+        profile.lockdown = Some(false);
+
+        // Without a system installation there is no database to record to, nor monitor to
+        // notify, so the only policy we can apply is the allowlist embedded in the package.
+        let allowlist = Allowlist::read(&profile_path).unwrap_or_default();
+        profile.seccomp = Some(match profile.seccomp.unwrap_or_default() {
+            SeccompPolicy::Enforcing | SeccompPolicy::Notifying if !allowlist.is_empty() => {
+                SeccompPolicy::Enforcing
+            }
+            SeccompPolicy::Disabled => SeccompPolicy::Disabled,
+            policy => {
+                warn!(
+                    "{policy} SECCOMP requires a system installation, and is disabled for this package"
+                );
+                SeccompPolicy::Disabled
+            }
+        });
+
+        package = Some((
+            Package {
+                allowlist,
+                ..Default::default()
+            },
+            true,
+        ));
         (profile, hash, profile_args)
     } else {
         let (profile, hash) = Profile::new(&name, args.config.take(), Some(&mut args), false)?;
@@ -372,6 +424,9 @@ pub fn setup<'a>(
         instance: &instance,
         run: args,
         package,
+        vault: None,
+        lock: None,
+        proxies: Vec::new(),
     };
 
     timer!("::proxy", proxy::setup(&mut a))?;
@@ -380,8 +435,10 @@ pub fn setup<'a>(
     timer!("::fab", fab::setup(&mut a))?;
     timer!("::file", files::setup(&mut a))?;
 
-    if a.package.is_none() {
-        timer!("::syscalls", syscalls::setup(&a))?;
+    match a.package {
+        None => timer!("::syscalls", syscalls::setup(&a))?,
+        Some((_, true)) => timer!("::syscalls", syscalls::package(&a))?,
+        Some((_, false)) => {}
     }
 
     // If we're dry-running, and are running under a single profile, flush as
@@ -404,6 +461,9 @@ pub fn setup<'a>(
         post,
         profile: a.profile,
         package: a.package,
+        vault: a.vault,
+        lock: a.lock,
+        proxies: a.proxies,
         instance,
         home,
         sys_dir,
@@ -1,5 +1,6 @@
 //! Antimony uses xdg-dbus-proxy to proxy the user bus. It does this by spawning an associated process
-//! that hooks onto the sandbox's user bus socket, and mediating the calls that come in.
+//! that hooks onto the sandbox's user bus socket, and mediating the calls that come in. The system bus
+//! and the accessibility bus can be proxied the same way, each by their own instance.
 
 use crate::{
     fab::{
@@ -11,11 +12,17 @@ use crate::{
         Set,
         env::{CACHE_DIR, RUNTIME_DIR, RUNTIME_STR, SESSION_BUS},
         package::Package,
-        profile::{Profile, ipc::Portal, ns::Namespace},
+        permissions,
+        profile::{
+            Profile,
+            ipc::{Ipc, Portal},
+            ns::Namespace,
+        },
     },
     timer,
 };
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use dbus::blocking::LocalConnection;
 use inotify::WatchMask;
 use rayon::prelude::*;
 use spawn::{Spawner, StreamMode};
@@ -24,12 +31,60 @@ use std::{
     io::Write,
     os::fd::AsRawFd,
     path::Path,
+    time::Duration,
 };
 use temp::Temp;
 use user::as_effective;
 
+/// Where the host's system bus lives.
+static SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+
+/// The bus a proxy mediates.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    /// The user's session bus.
+    Session,
+
+    /// The system bus.
+    System,
+
+    /// The AT-SPI accessibility bus.
+    Accessibility,
+}
+impl Bus {
+    /// The name of the proxy, used for its directory within the instance, and its cache.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Session => "proxy",
+            Self::System => "proxy-system",
+            Self::Accessibility => "proxy-a11y",
+        }
+    }
+
+    /// The host socket of the bus. The session bus is instead reached through its address.
+    fn socket(self) -> Result<Option<String>> {
+        match self {
+            Self::Session => Ok(None),
+            Self::System => Ok(Some(SYSTEM_BUS.to_owned())),
+            Self::Accessibility => accessibility_bus().map(Some),
+        }
+    }
+}
+
+/// Ask the session bus where the accessibility bus is.
+fn accessibility_bus() -> Result<String> {
+    let connection = LocalConnection::new_session()?;
+    let proxy = connection.with_proxy("org.a11y.Bus", "/org/a11y/bus", Duration::from_secs(5));
+    let (address,): (String,) = proxy.method_call("org.a11y.Bus", "GetAddress", ())?;
+    address
+        .split(',')
+        .find_map(|part| part.strip_prefix("unix:path="))
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| anyhow!("Unsupported accessibility bus address: {address}"))
+}
+
 /// Get the Spawner used to run Proxy.
-#[allow(clippy::ref_option)]
+#[allow(clippy::ref_option, clippy::too_many_arguments)]
 pub fn run(
     sys_dir: &Path,
     profile: &Profile,
@@ -37,12 +92,13 @@ pub fn run(
     info: &Path,
     id: &str,
     package: &Option<(Package, bool)>,
+    bus: Bus,
 ) -> Result<Spawner> {
     let runtime = RUNTIME_DIR.to_string_lossy();
     let cache = CACHE_DIR.join(".proxy");
     let sof = cache.join("sof");
     let app_dir = RUNTIME_DIR.join("app").join(id);
-    let proxy = instance.full().join("proxy");
+    let proxy = instance.full().join(bus.name());
     let harden = profile
         .ipc
         .as_ref()
@@ -75,9 +131,10 @@ pub fn run(
     }
 
     let path = which::which("xdg-dbus-proxy")?;
+    let proxy_str = proxy.to_string_lossy();
     #[rustfmt::skip]
         let proxy = Spawner::new("bwrap")?
-        .name("proxy")
+        .name(bus.name())
         .error(StreamMode::Log(log::Level::Error))
         .mode(user::Mode::Real).args([
             "--new-session",
@@ -89,11 +146,25 @@ pub fn run(
             "--unshare-user",
             "--die-with-parent",
             "--dir", &runtime,
+        ]);
+
+    // Only the session proxy needs to identify the application to portals.
+    let socket = bus.socket()?;
+    if let Some(socket) = &socket {
+        #[rustfmt::skip]
+        proxy.args_i([
+            "--ro-bind", socket, socket,
+            "--bind", &proxy_str, "/proxy",
+        ]);
+    } else {
+        #[rustfmt::skip]
+        proxy.args_i([
             "--bind", &format!("{runtime}/bus"), &format!("{runtime}/bus"),
             "--ro-bind", &info.to_string_lossy(), "/.flatpak-info",
             "--symlink", "/.flatpak-info", &format!("{runtime}/flatpak-info"),
-            "--bind", &proxy.to_string_lossy(), &format!("{runtime}/app/{id}"),
+            "--bind", &proxy_str, &format!("{runtime}/app/{id}"),
         ]);
+    }
 
     // If we are running a package, just mount its system libraries.
     if is_package {
@@ -111,13 +182,23 @@ pub fn run(
         mount_roots("", &proxy)?;
     }
 
-    proxy.args_i([
-        "--",
-        "/usr/bin/xdg-dbus-proxy",
-        SESSION_BUS.as_str(),
-        &app_dir.join("bus").to_string_lossy(),
-        "--filter",
-    ]);
+    if let Some(socket) = &socket {
+        proxy.args_i([
+            "--",
+            "/usr/bin/xdg-dbus-proxy",
+            &format!("unix:path={socket}"),
+            "/proxy/bus",
+            "--filter",
+        ]);
+    } else {
+        proxy.args_i([
+            "--",
+            "/usr/bin/xdg-dbus-proxy",
+            SESSION_BUS.as_str(),
+            &app_dir.join("bus").to_string_lossy(),
+            "--filter",
+        ]);
+    }
 
     if log::log_enabled!(log::Level::Debug) {
         proxy.arg_i("--log");
@@ -125,7 +206,7 @@ pub fn run(
     }
 
     // We cache the proxy's arguments directly.
-    let cache = sys_dir.join("proxy.cache");
+    let cache = sys_dir.join(format!("{}.cache", bus.name()));
     if cache.exists() {
         proxy.cache_read(&cache)?;
     } else {
@@ -136,7 +217,48 @@ pub fn run(
             format!("--call={portal}=org.freedesktop.DBus.Properties.*@{path}")
         };
 
-        if let Some(ipc) = &profile.ipc {
+        if bus == Bus::Accessibility {
+            // Mirrors Flatpak: the application may register itself with the registry,
+            // and answer those that query it, but cannot reach other applications.
+            let registry = "org.a11y.atspi.Registry";
+            let controller = "/org/a11y/atspi/registry/deviceeventcontroller";
+            proxy.arg_i("--sloppy-names");
+            for call in ["Embed", "Unembed"] {
+                proxy.arg_i(format!(
+                    "--call={registry}=org.a11y.atspi.Socket.{call}@/org/a11y/atspi/accessible/root"
+                ));
+            }
+            proxy.arg_i(format!(
+                "--call={registry}=org.a11y.atspi.Registry.GetRegisteredEvents@/org/a11y/atspi/registry"
+            ));
+            for call in [
+                "GetKeystrokeListeners",
+                "GetDeviceEventListeners",
+                "NotifyListenersSync",
+                "NotifyListenersAsync",
+            ] {
+                proxy.arg_i(format!(
+                    "--call={registry}=org.a11y.atspi.DeviceEventController.{call}@{controller}"
+                ));
+            }
+            for signal in ["EventListenerRegistered", "EventListenerDeregistered"] {
+                proxy.arg_i(format!(
+                    "--broadcast={registry}=org.a11y.atspi.Registry.{signal}@/org/a11y/atspi/registry"
+                ));
+            }
+        } else if let Some(ipc) = &profile.ipc
+            && bus == Bus::System
+        {
+            for name in &ipc.system_sees {
+                proxy.args_i([format!("--see={name}"), permit_call(name)]);
+            }
+            for name in &ipc.system_talks {
+                proxy.args_i([format!("--talk={name}"), permit_call(name)]);
+            }
+            for rule in &ipc.system_calls {
+                proxy.arg_i(format!("--call={rule}"));
+            }
+        } else if let Some(ipc) = &profile.ipc {
             if !ipc.portals.is_empty() {
                 let desktop = "org.freedesktop.portal.Desktop";
                 let path = "/org/freedesktop/portal/desktop";
@@ -166,8 +288,11 @@ pub fn run(
             for portal in &ipc.owns {
                 proxy.args_i([format!("--own={portal}"), permit_call(portal)]);
             }
-            for portal in &ipc.calls {
-                proxy.arg_i(format!("--call={portal}"));
+            for rule in &ipc.calls {
+                proxy.arg_i(format!("--call={rule}"));
+            }
+            for rule in &ipc.broadcasts {
+                proxy.arg_i(format!("--broadcast={rule}"));
             }
         }
         as_effective!(proxy.cache_write(&cache))??;
@@ -186,6 +311,26 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
         }
     };
 
+    // Grants are stored under the ID the proxy gives the sandbox, so they only apply to
+    // portals reached through it. Rather than drop them, refuse to run without it.
+    if !ipc.portal_permissions.is_empty() {
+        permissions::validate(&ipc.portal_permissions)?;
+        if ipc.disable.unwrap_or(false) || ipc.user_bus.unwrap_or(false) {
+            return Err(anyhow!(
+                "Portal permissions only apply through the proxy, which is not used with `disable` or `user_bus`"
+            ));
+        }
+        if let Some(portal) = ipc
+            .portal_permissions
+            .keys()
+            .find(|portal| !ipc.portals.contains(*portal))
+        {
+            return Err(anyhow!(
+                "{portal} has a permission, but is not in `portals`"
+            ));
+        }
+    }
+
     if ipc.disable.unwrap_or(false) {
         return Ok(());
     }
@@ -220,6 +365,7 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
         || !ipc.talks.is_empty()
         || !ipc.sees.is_empty()
         || !ipc.calls.is_empty()
+        || !ipc.broadcasts.is_empty()
     {
         let proxy = timer!(
             "::run",
@@ -229,7 +375,8 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
                 args.instance,
                 &info,
                 id,
-                &args.package
+                &args.package,
+                Bus::Session
             )
         )?;
 
@@ -249,6 +396,13 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
                 )?;
             }
 
+            // Seed the permission store before the application can ask for anything.
+            if !ipc.portal_permissions.is_empty()
+                && let Err(e) = permissions::apply(id, &ipc.portal_permissions)
+            {
+                log::warn!("Failed to set portal permissions: {e}");
+            }
+
             // Create the flatpak-info, but don't bother if we're running dry.
             let namespaces = args.profile.namespaces.clone();
 
@@ -304,6 +458,8 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
             );
 
             args.handle.associate(proxy.spawn()?);
+        } else if args.run.record {
+            args.proxies.push(proxy);
         }
 
         args.handle.args_i([
@@ -313,5 +469,75 @@ pub fn setup(args: &mut super::Args) -> Result<()> {
         ]);
     }
 
+    // The filtered system bus is mounted over the host's, so it must come last.
+    if ipc.system_proxy() {
+        secondary(args, &ipc, &info, Bus::System, SYSTEM_BUS)?;
+    }
+
+    // Toolkits find the accessibility bus through the environment, which saves
+    // having to let them ask the session bus where it is.
+    if ipc.accessibility.unwrap_or(false) {
+        let dest = format!("{runtime}/at-spi/bus");
+        match secondary(args, &ipc, &info, Bus::Accessibility, &dest) {
+            Ok(()) => args.handle.args_i([
+                "--setenv",
+                "AT_SPI_BUS_ADDRESS",
+                &format!("unix:path={dest}"),
+            ]),
+            Err(e) => log::warn!("Accessibility bus unavailable: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a proxy for a bus other than the session bus, and mount its socket at dest.
+fn secondary(args: &mut super::Args, ipc: &Ipc, info: &Path, bus: Bus, dest: &str) -> Result<()> {
+    let proxy = timer!(
+        "::secondary",
+        run(
+            &args.sys_dir,
+            &args.profile,
+            args.instance,
+            info,
+            &args.id,
+            &args.package,
+            bus
+        )
+    )?;
+
+    if !args.run.dry {
+        if ipc.harden.unwrap_or_default()
+            && args.package.is_none()
+            && let Some(policy) = args.profile.seccomp
+        {
+            syscalls::install_filter(
+                "xdg-dbus-proxy",
+                args.instance,
+                policy,
+                &Set::default(),
+                &proxy,
+                &args.handle,
+                false,
+            )?;
+        }
+
+        let dir = args.instance.full().join(bus.name());
+        args.watches
+            .insert(args.inotify.watches().add(&dir, WatchMask::CREATE)?);
+        args.handle.associate(proxy.spawn()?);
+    } else if args.run.record {
+        args.proxies.push(proxy);
+    }
+
+    args.handle.args_i([
+        "--ro-bind",
+        &format!(
+            "{}/{}/bus",
+            args.instance.full().to_string_lossy(),
+            bus.name()
+        ),
+        dest,
+    ]);
     Ok(())
 }
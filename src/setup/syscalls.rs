@@ -95,3 +95,22 @@ pub fn setup(args: &super::Args) -> Result<()> {
     }
     Ok(())
 }
+
+// Install the allowlist embedded in a package, if we are enforcing it.
+pub fn package(args: &super::Args) -> Result<()> {
+    if args.run.dry || args.profile.seccomp != Some(SeccompPolicy::Enforcing) {
+        return Ok(());
+    }
+
+    if let Some((package, _)) = &args.package {
+        let allowlist = &package.allowlist;
+        let (filter, fd) = syscalls::from_allowlist(
+            &allowlist.syscalls,
+            &allowlist.bwrap,
+            &args.sys_dir.join(".seccomp"),
+        )?;
+        args.handle.seccomp_i(filter);
+        args.handle.fd_arg_i("--seccomp", fd);
+    }
+    Ok(())
+}
@@ -4,6 +4,7 @@
 use crate::shared::{
     Map, Set, edit,
     env::{AT_HOME, USER_NAME},
+    store::Backend,
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -47,6 +48,7 @@ pub struct ConfigFile {
     pub system_mode: Option<bool>,
     pub auto_refresh: Option<bool>,
     pub privileged_users: Option<Set<String>>,
+    pub store: Option<Backend>,
 
     #[serde(skip_serializing_if = "Set::is_empty", default = "Set::default")]
     pub library_roots: Set<String>,
@@ -77,6 +79,11 @@ impl ConfigFile {
             .is_some_and(|users| users.contains(USER_NAME.as_str()))
     }
 
+    #[must_use]
+    pub fn store(&self) -> Backend {
+        self.store.unwrap_or_default()
+    }
+
     #[must_use]
     pub const fn library_roots(&self) -> &Set<String> {
         &self.library_roots
@@ -104,6 +111,10 @@ impl ConfigFile {
         switch(&mut self.system_mode, config.system_mode);
         switch(&mut self.auto_refresh, config.auto_refresh);
 
+        if config.store.is_some() {
+            self.store = config.store;
+        }
+
         self.library_roots.extend(config.library_roots);
         self.environment.extend(config.environment);
 
@@ -130,6 +141,7 @@ impl ConfigFile {
             system_mode: None,
             auto_refresh: None,
             privileged_users: None,
+            store: None,
             library_roots: Set::default(),
             environment: Map::default(),
         }
@@ -0,0 +1,198 @@
+//! Delta updates for packages.
+//!
+//! A delta holds the entries that were changed or added between two versions of a
+//! package, alongside the entries that were removed. Profiles, symlinks and metadata
+//! are small, and are always included in full. Both versions are identified by their
+//! content hash, so a delta can only be applied to the package it was made from, and
+//! the result is verified before it is used.
+//!
+//! Applied updates are unpacked into `$XDG_DATA_HOME/antimony/updates`, alongside the
+//! updated payload (So further deltas can be applied), and the digest of the package
+//! they were applied to. If that package is replaced, the update is ignored.
+
+use crate::shared::{
+    Map, Set,
+    env::DATA_HOME,
+    package::{Package, encode},
+    signature::{self, Trailer},
+};
+use anyhow::{Result, anyhow};
+use bilrost::{Message, OwnedMessage};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Marks the start of a delta file.
+pub static DELTA_MARKER: [u8; 7] = *b"\0DLT\0\0\0";
+
+/// The compression level of installed payloads. They never leave the machine, so
+/// we favor speed.
+static LEVEL: i32 = 3;
+
+/// Where applied updates are stored.
+pub static UPDATE_DIR: LazyLock<PathBuf> =
+    LazyLock::new(|| DATA_HOME.join("antimony").join("updates"));
+
+/// Entries removed from a field of the package.
+#[derive(Message, Default)]
+pub struct Removal {
+    /// The name of the field, as in `Package::contents`.
+    pub field: String,
+
+    /// The paths removed from it.
+    pub paths: Vec<String>,
+}
+
+/// The difference between two versions of a package.
+#[derive(Message, Default)]
+pub struct Delta {
+    /// The digest of the package the delta applies to.
+    pub base: String,
+
+    /// The digest of the package once the delta is applied.
+    pub digest: String,
+
+    /// The new package, holding only the changed and added entries.
+    pub package: Package,
+
+    /// Entries that no longer exist in the new package.
+    pub removed: Vec<Removal>,
+}
+impl Delta {
+    /// Construct the delta that updates `base` to `package`.
+    #[must_use]
+    pub fn new(base: &Package, mut package: Package) -> Self {
+        let digest = package.digest();
+        let mut removed = Vec::new();
+
+        for ((field, old), (_, new)) in base.contents().into_iter().zip(package.contents_mut()) {
+            let paths: Vec<String> = old
+                .keys()
+                .filter(|path| !new.contains_key(*path))
+                .cloned()
+                .collect();
+            if !paths.is_empty() {
+                removed.push(Removal {
+                    field: field.to_owned(),
+                    paths,
+                });
+            }
+            new.retain(|path, content| old.get(path) != Some(&*content));
+        }
+
+        Self {
+            base: base.digest(),
+            digest,
+            package,
+            removed,
+        }
+    }
+
+    /// Apply the delta to the package it was made from.
+    pub fn apply(self, mut base: Package) -> Result<Package> {
+        if base.digest() != self.base {
+            return Err(anyhow!(
+                "Delta does not apply to this version of {}",
+                base.name
+            ));
+        }
+
+        let removed: Map<&str, Set<&str>> = self
+            .removed
+            .iter()
+            .map(|removal| {
+                (
+                    removal.field.as_str(),
+                    removal.paths.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+
+        let mut package = self.package;
+        for ((field, old), (_, new)) in base.contents_mut().into_iter().zip(package.contents_mut())
+        {
+            let removed = removed.get(field);
+            for (path, content) in old.drain() {
+                if removed.is_none_or(|removed| !removed.contains(path.as_str())) {
+                    new.entry(path).or_insert(content);
+                }
+            }
+        }
+
+        if package.digest() != self.digest {
+            return Err(anyhow!(
+                "Updated package does not match the delta! Corrupted Delta!"
+            ));
+        }
+        Ok(package)
+    }
+
+    /// Write the delta to a file, optionally signing it.
+    pub fn write(&self, path: &Path, level: i32, key: Option<&Path>) -> Result<()> {
+        let payload = encode(self, level)?;
+        let mut file = File::create(path)?;
+        file.write_all(&DELTA_MARKER)?;
+        file.write_all(&payload)?;
+        if let Some(key) = key {
+            file.write_all(&signature::sign(&payload, key)?.to_bytes())?;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Read a delta from a file, verifying its signature.
+    pub fn read(path: &Path, insecure: bool) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let Some(rest) = bytes.strip_prefix(&DELTA_MARKER) else {
+            return Err(anyhow!("{} is not a package delta", path.display()));
+        };
+
+        let trailer = Trailer::parse(rest);
+        let payload = match &trailer {
+            Some(trailer) => trailer
+                .payload(rest)
+                .ok_or_else(|| anyhow!("Signature Trailer Invalid! Corrupted Delta!"))?,
+            None => rest,
+        };
+        signature::verify(payload, trailer.as_ref(), insecure)?;
+        Ok(Self::decode(zstd::decode_all(payload)?.as_slice())?)
+    }
+}
+
+/// Get the applied update for a package, if it was applied to the package with
+/// the digest `source`.
+#[must_use]
+pub fn updated(name: &OsStr, source: &[u8]) -> Option<PathBuf> {
+    let dir = UPDATE_DIR.join(name);
+    (fs::read(dir.join(".source")).ok()? == source).then_some(dir)
+}
+
+/// Read the package of an applied update.
+pub fn installed(dir: &Path) -> Result<Package> {
+    let bytes = zstd::decode_all(File::open(dir.join(".package"))?)?;
+    Ok(Package::decode(bytes.as_slice())?)
+}
+
+/// Install an updated package. It is unpacked beside the existing update, which
+/// is only replaced once the unpack succeeds.
+pub fn install(name: &OsStr, source: &[u8], package: &Package) -> Result<PathBuf> {
+    let dir = UPDATE_DIR.join(name);
+    let staging = UPDATE_DIR.join(format!(".{}", name.to_string_lossy()));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    package.unpack(&staging)?;
+    fs::write(staging.join(".package"), encode(package, LEVEL)?)?;
+    fs::write(staging.join(".source"), source)?;
+
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&staging, &dir)?;
+    Ok(dir)
+}
@@ -406,6 +406,10 @@ fn add_feature(profile: &mut Profile, map: &Map<&str, String>, mut feature: Feat
             Some(false) | None => ipc.user_bus,
             Some(true) => Some(true),
         };
+        p_ipc.accessibility = match p_ipc.accessibility {
+            Some(false) | None => ipc.accessibility,
+            Some(true) => Some(true),
+        };
 
         // Conversely, if a feature or profile has explicitly set
         // disable to false for compatibility, you cannot enable it.
@@ -442,6 +446,9 @@ fn add_feature(profile: &mut Profile, map: &Map<&str, String>, mut feature: Feat
         if !ipc.portals.is_empty() {
             p_ipc.portals.extend(ipc.portals);
         }
+        for (portal, permission) in ipc.portal_permissions {
+            p_ipc.portal_permissions.entry(portal).or_insert(permission);
+        }
         if !ipc.sees.is_empty() {
             let formatted = format_all(ipc.sees);
             if !formatted.is_empty() {
@@ -463,6 +470,18 @@ fn add_feature(profile: &mut Profile, map: &Map<&str, String>, mut feature: Feat
         if !ipc.calls.is_empty() {
             p_ipc.calls.extend(ipc.calls);
         }
+        if !ipc.broadcasts.is_empty() {
+            p_ipc.broadcasts.extend(ipc.broadcasts);
+        }
+        if !ipc.system_sees.is_empty() {
+            p_ipc.system_sees.extend(format_all(ipc.system_sees));
+        }
+        if !ipc.system_talks.is_empty() {
+            p_ipc.system_talks.extend(format_all(ipc.system_talks));
+        }
+        if !ipc.system_calls.is_empty() {
+            p_ipc.system_calls.extend(ipc.system_calls);
+        }
     }
 
     if let Some(env) = feature.environment.take() {
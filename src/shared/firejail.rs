@@ -0,0 +1,444 @@
+//! Convert Firejail profiles into Antimony profiles.
+//!
+//! Firejail starts from the host and takes away, whereas Antimony starts from nothing
+//! and adds. Whitelists, bus rules, and the like translate onto the profile, while
+//! blacklists are simply unnecessary. What's left is reported alongside why.
+
+use crate::shared::{
+    Set,
+    env::{CONFIG_HOME, HOME},
+    profile::{
+        Profile,
+        files::{FileMode, Files},
+        ipc::{Ipc, Rule},
+        seccomp::SeccompPolicy,
+    },
+};
+use anyhow::Result;
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+};
+
+/// Directives that restrict what Antimony never provides to begin with.
+static COVERED: [&str; 23] = [
+    "caps.drop",
+    "disable-mnt",
+    "ipc-namespace",
+    "machine-id",
+    "mkdir",
+    "mkfile",
+    "no3d",
+    "nodvd",
+    "nogroups",
+    "noinput",
+    "nonewprivs",
+    "noprinters",
+    "noroot",
+    "nosound",
+    "notv",
+    "nou2f",
+    "novideo",
+    "private",
+    "private-cache",
+    "private-dev",
+    "private-tmp",
+    "quiet",
+    "shell",
+];
+
+/// Directives that hide things from the sandbox, which Antimony doesn't need.
+static HIDING: [&str; 6] = [
+    "blacklist",
+    "blacklist-nolog",
+    "deny",
+    "noblacklist",
+    "nodeny",
+    "nowhitelist",
+];
+
+/// Where a file is placed in the profile.
+#[derive(Debug, PartialEq, Eq)]
+enum Place {
+    /// Relative to the home.
+    User(String),
+
+    /// A system file.
+    Platform(String),
+
+    /// A system file required by the application.
+    Resource(String),
+
+    /// A file in the runtime directory.
+    Runtime(String),
+}
+
+/// The state of a conversion.
+#[derive(Default)]
+struct Conversion {
+    /// The profile being built.
+    profile: Profile,
+
+    /// Whitelisted files, and whether they are writable.
+    files: Vec<(String, bool)>,
+
+    /// Paths made read-only.
+    read_only: Set<String>,
+
+    /// Whether the network was removed.
+    no_network: bool,
+
+    /// How many hiding directives were skipped.
+    hidden: usize,
+
+    /// Directives without an equivalent, and why.
+    untranslated: Vec<String>,
+
+    /// Files that have already been read, to guard against include loops.
+    visited: Set<PathBuf>,
+}
+
+/// Find an included file, either beside the profile or in Firejail's directories.
+fn find_include(name: &str, dir: &Path) -> Option<PathBuf> {
+    let name = name
+        .replace("${CFG}", "/etc/firejail")
+        .replace("${HOME}", HOME.as_str());
+    let path = Path::new(&name);
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+
+    [
+        dir.to_path_buf(),
+        CONFIG_HOME.join("firejail"),
+        PathBuf::from("/etc/firejail"),
+    ]
+    .into_iter()
+    .map(|dir| dir.join(path))
+    .find(|path| path.exists())
+}
+
+/// Translate a Firejail path into where it belongs in the profile.
+fn place(path: &str) -> Option<Place> {
+    if path.contains('*') {
+        return None;
+    }
+
+    for (variable, dir) in [
+        ("${DESKTOP}", "Desktop"),
+        ("${DOCUMENTS}", "Documents"),
+        ("${DOWNLOADS}", "Downloads"),
+        ("${MUSIC}", "Music"),
+        ("${PICTURES}", "Pictures"),
+        ("${VIDEOS}", "Videos"),
+    ] {
+        if let Some(rest) = path.strip_prefix(variable) {
+            return Some(Place::User(format!("{dir}{rest}")));
+        }
+    }
+
+    if let Some(rest) = path
+        .strip_prefix("${HOME}")
+        .or_else(|| path.strip_prefix('~'))
+    {
+        let rest = rest.trim_start_matches('/');
+        return Some(Place::User(if rest.is_empty() {
+            "~".to_owned()
+        } else {
+            format!("~/{rest}")
+        }));
+    }
+
+    if let Some(rest) = path.strip_prefix("${RUNUSER}") {
+        return Some(Place::Runtime(format!("$XDG_RUNTIME_DIR{rest}")));
+    }
+
+    if path.starts_with("/usr/share") {
+        Some(Place::Resource(path.to_owned()))
+    } else if path.starts_with('/') && !path.contains("${") {
+        Some(Place::Platform(path.to_owned()))
+    } else {
+        None
+    }
+}
+
+impl Conversion {
+    /// Note a directive that could not be translated.
+    fn untranslated(&mut self, line: &str, reason: &str) {
+        self.untranslated.push(format!("{line}: {reason}"));
+    }
+
+    /// Get the IPC of the profile.
+    fn ipc(&mut self) -> &mut Ipc {
+        self.profile.ipc.get_or_insert_default()
+    }
+
+    /// Parse a bus rule, noting it if it is invalid.
+    fn rule(&mut self, line: &str, rule: &str) -> Option<Rule> {
+        match rule.parse() {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                self.untranslated(line, &e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Read a profile, or an included file.
+    fn read(&mut self, path: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(path)?;
+        if !self.visited.insert(canonical) {
+            return Ok(());
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for line in fs::read_to_string(path)?.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (directive, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            self.directive(line, directive, value, dir)?;
+        }
+        Ok(())
+    }
+
+    /// Translate a single directive.
+    fn directive(&mut self, line: &str, directive: &str, value: &str, dir: &Path) -> Result<()> {
+        match directive {
+            "include" => {
+                // Local overrides are optional, and usually absent.
+                if let Some(path) = find_include(value, dir) {
+                    self.read(&path)?;
+                } else if !value.ends_with(".local") {
+                    self.untranslated(line, "The included file could not be found");
+                }
+            }
+            "whitelist" | "whitelist-ro" => {
+                if place(value).is_some() {
+                    self.files
+                        .push((value.to_owned(), directive == "whitelist"));
+                } else {
+                    self.untranslated(line, "Wildcards and unknown variables cannot be translated");
+                }
+            }
+            "read-only" => {
+                self.read_only.insert(value.to_owned());
+            }
+            "read-write" => {
+                self.read_only.remove(value);
+            }
+            "net" if value == "none" => self.no_network = true,
+            "dbus-user" if value == "none" => self.ipc().disable = Some(true),
+            "dbus-user" | "dbus-system" if value == "filter" || value == "none" => {}
+            "dbus-user.talk" => {
+                self.ipc().talks.insert(value.to_owned());
+            }
+            "dbus-user.own" => {
+                self.ipc().owns.insert(value.to_owned());
+            }
+            "dbus-user.see" => {
+                self.ipc().sees.insert(value.to_owned());
+            }
+            "dbus-user.call" => {
+                if let Some(rule) = self.rule(line, value) {
+                    self.ipc().calls.insert(rule);
+                }
+            }
+            "dbus-user.broadcast" => {
+                if let Some(rule) = self.rule(line, value) {
+                    self.ipc().broadcasts.insert(rule);
+                }
+            }
+            "dbus-system.talk" => {
+                self.ipc().system_talks.insert(value.to_owned());
+            }
+            "dbus-system.see" => {
+                self.ipc().system_sees.insert(value.to_owned());
+            }
+            "dbus-system.call" => {
+                if let Some(rule) = self.rule(line, value) {
+                    self.ipc().system_calls.insert(rule);
+                }
+            }
+            "private-bin" => self.profile.binaries.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|b| !b.is_empty())
+                    .map(ToOwned::to_owned),
+            ),
+            "private-etc" => {
+                let files = self.profile.files.get_or_insert_default();
+                files
+                    .platform
+                    .entry(FileMode::ReadOnly)
+                    .or_default()
+                    .extend(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|f| !f.is_empty())
+                            .map(|f| format!("/etc/{}", f.trim_start_matches("/etc/"))),
+                    );
+            }
+            "env" => {
+                if let Some((key, val)) = value.split_once('=') {
+                    self.profile
+                        .environment
+                        .insert(key.to_owned(), val.to_owned());
+                }
+            }
+            "seccomp" | "seccomp.drop" | "seccomp.keep" | "seccomp.block-secondary" => {
+                if self.profile.seccomp.is_none() {
+                    self.profile.seccomp = Some(SeccompPolicy::Permissive);
+                    self.untranslated(
+                        line,
+                        "Firejail's filter has no equivalent. The profile starts Permissive, so Antimony can learn its own policy",
+                    );
+                }
+            }
+            "protocol" => self.untranslated(
+                line,
+                "Socket families are not filtered. The network feature decides on inet and inet6",
+            ),
+            "net" => self.untranslated(line, "Only net none has an equivalent"),
+            "ignore" => self.untranslated(line, "Ignoring directives has no equivalent"),
+            directive if HIDING.contains(&directive) => {
+                self.hidden = self.hidden.saturating_add(1);
+            }
+            directive if COVERED.contains(&directive) => {}
+            _ => self.untranslated(line, "No equivalent"),
+        }
+        Ok(())
+    }
+
+    /// Place the whitelisted files, and finish the profile.
+    fn finish(mut self) -> (Profile, Vec<String>) {
+        for (path, writable) in mem::take(&mut self.files) {
+            let mode = if writable && !self.read_only.contains(&path) {
+                FileMode::ReadWrite
+            } else {
+                FileMode::ReadOnly
+            };
+
+            let files: &mut Files = self.profile.files.get_or_insert_default();
+            match place(&path) {
+                Some(Place::User(file)) => {
+                    files.user.entry(mode).or_default().insert(file);
+                }
+                Some(Place::Platform(file)) => {
+                    files.platform.entry(mode).or_default().insert(file);
+                }
+                Some(Place::Resource(file)) => {
+                    files.resources.entry(mode).or_default().insert(file);
+                }
+                Some(Place::Runtime(file)) => {
+                    files.runtime.entry(mode).or_default().insert(file);
+                }
+                None => {}
+            }
+        }
+
+        // Firejail provides the network unless told otherwise.
+        if !self.no_network {
+            self.profile.features.insert("network".to_owned());
+        }
+
+        if self.hidden > 0 {
+            self.untranslated.push(format!(
+                "{} blacklist directives: Not needed, nothing is visible unless it is provided",
+                self.hidden
+            ));
+        }
+        (self.profile, self.untranslated)
+    }
+}
+
+/// Convert a Firejail profile, and everything it includes, into a profile. Returns the
+/// name of the profile, the profile, and the directives that could not be translated.
+pub fn convert(path: &Path) -> Result<(String, Profile, Vec<String>)> {
+    let name = path
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut conversion = Conversion::default();
+    conversion.read(path)?;
+    let (mut profile, untranslated) = conversion.finish();
+    profile.notes = Some(format!(
+        "Converted from the Firejail profile {}.",
+        path.display()
+    ));
+    Ok((name, profile, untranslated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_paths() {
+        for (path, expected) in [
+            ("${HOME}", Place::User("~".to_owned())),
+            ("~", Place::User("~".to_owned())),
+            ("${HOME}/", Place::User("~".to_owned())),
+            (
+                "${HOME}/.config/app",
+                Place::User("~/.config/app".to_owned()),
+            ),
+            ("~/.cache/app", Place::User("~/.cache/app".to_owned())),
+            ("${DOWNLOADS}", Place::User("Downloads".to_owned())),
+            ("${PICTURES}/app", Place::User("Pictures/app".to_owned())),
+            (
+                "${RUNUSER}/pipewire-0",
+                Place::Runtime("$XDG_RUNTIME_DIR/pipewire-0".to_owned()),
+            ),
+            (
+                "/usr/share/app",
+                Place::Resource("/usr/share/app".to_owned()),
+            ),
+            ("/opt/app", Place::Platform("/opt/app".to_owned())),
+        ] {
+            assert_eq!(place(path), Some(expected), "{path}");
+        }
+
+        for path in [
+            "${HOME}/.config/*",
+            "${PATH}/app",
+            "/opt/${APP}",
+            "relative",
+        ] {
+            assert_eq!(place(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn follows_include_cycles() {
+        let dir = temp::Builder::new()
+            .create::<temp::Directory>()
+            .expect("Failed to create temporary directory");
+        let dir = dir.full();
+        fs::write(
+            dir.join("app.profile"),
+            "include common.inc\nwhitelist ${DOWNLOADS}\n",
+        )
+        .expect("Failed to write profile");
+        fs::write(
+            dir.join("common.inc"),
+            "include app.profile\nprivate-bin app\n",
+        )
+        .expect("Failed to write include");
+
+        let (name, profile, untranslated) =
+            convert(&dir.join("app.profile")).expect("Failed to convert");
+        assert_eq!(name, "app");
+        assert!(profile.binaries.contains("app"));
+        assert!(
+            profile
+                .files
+                .is_some_and(|files| files.user.contains_key(&FileMode::ReadWrite))
+        );
+        assert!(untranslated.is_empty());
+    }
+}
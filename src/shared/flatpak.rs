@@ -0,0 +1,405 @@
+//! Convert the permissions of Flatpak applications into profiles.
+//!
+//! Flatpak describes permissions either as `finish-args` in a manifest, which are
+//! the command line arguments of `flatpak build-finish`, or as the keyfile in an
+//! installed application's `metadata`. Both are normalized to the arguments, and
+//! then mapped onto the equivalent features, files, and busses.
+
+use crate::shared::profile::{Profile, files::FileMode, ipc::Ipc};
+use anyhow::{Result, anyhow};
+use std::{fs, path::Path};
+
+/// An application's permissions, as the arguments to `flatpak build-finish`.
+#[derive(Default)]
+struct Permissions {
+    /// The application ID.
+    id: Option<String>,
+
+    /// The binary the application runs.
+    command: Option<String>,
+
+    /// The arguments.
+    args: Vec<String>,
+}
+
+/// Strip the quotes and punctuation surrounding a value in JSON or YAML.
+fn clean(value: &str) -> &str {
+    value
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches([',', ']'])
+        .trim_start_matches("- ")
+        .trim()
+        .trim_matches(['"', '\''])
+}
+
+/// Get a top-level scalar from a JSON or YAML manifest.
+fn field(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let line = line.trim();
+        let rest = line
+            .strip_prefix(&format!("\"{key}\""))
+            .or_else(|| line.strip_prefix(key))?;
+        let value = clean(rest.trim_start().strip_prefix(':')?);
+        (!value.is_empty()).then(|| value.to_owned())
+    })
+}
+
+/// Read the permissions of a JSON or YAML manifest. Rather than parse either format,
+/// the arguments are picked out of the list following `finish-args`.
+fn manifest(contents: &str) -> Permissions {
+    let mut args = Vec::new();
+    if let Some(start) = contents.find("finish-args") {
+        let list = &contents[start..];
+        let list = list.split_once(':').map_or(list, |(_, list)| list);
+        for (index, line) in list.lines().enumerate() {
+            let trimmed = line.trim();
+            if index > 0 && !trimmed.is_empty() && !trimmed.starts_with(['-', '"', '\'', '#', ']'])
+            {
+                break;
+            }
+            for arg in trimmed.split(',').map(clean) {
+                if arg.starts_with("--") {
+                    args.push(arg.to_owned());
+                }
+            }
+            if trimmed.contains(']') {
+                break;
+            }
+        }
+    }
+
+    Permissions {
+        id: field(contents, "app-id").or_else(|| field(contents, "id")),
+        command: field(contents, "command"),
+        args,
+    }
+}
+
+/// Read the permissions of an installed application's metadata keyfile.
+fn metadata(contents: &str) -> Permissions {
+    let mut permissions = Permissions::default();
+    let mut section = "";
+    for line in contents.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let list = |flag: &str| -> Vec<String> {
+            value
+                .split(';')
+                .filter(|v| !v.is_empty())
+                .map(|v| match v.strip_prefix('!') {
+                    Some(v) => format!("--no{flag}={v}"),
+                    None => format!("--{flag}={v}"),
+                })
+                .collect()
+        };
+
+        match (section, key) {
+            ("Application", "name") => permissions.id = Some(value.to_owned()),
+            ("Application", "command") => permissions.command = Some(value.to_owned()),
+            ("Context", "sockets") => permissions.args.extend(list("socket")),
+            ("Context", "shared") => permissions.args.extend(list("share")),
+            ("Context", "devices") => permissions.args.extend(list("device")),
+            ("Context", "filesystems") => permissions.args.extend(list("filesystem")),
+            ("Context", "persistent") => permissions.args.extend(list("persist")),
+            ("Context", "features") => permissions.args.extend(list("allow")),
+            ("Session Bus Policy", name) => permissions.args.push(format!("--{value}-name={name}")),
+            ("System Bus Policy", name) => {
+                permissions
+                    .args
+                    .push(format!("--system-{value}-name={name}"));
+            }
+            ("Environment", name) => permissions.args.push(format!("--env={name}={value}")),
+            _ => {}
+        }
+    }
+    permissions
+}
+
+/// Add a feature to the profile.
+fn feature(profile: &mut Profile, name: &str) -> bool {
+    profile.features.insert(name.to_owned());
+    true
+}
+
+/// Get the IPC of the profile.
+fn ipc(profile: &mut Profile) -> &mut Ipc {
+    profile.ipc.get_or_insert_default()
+}
+
+/// Where a translated filesystem belongs in the profile.
+enum Target {
+    /// Relative to the home.
+    User,
+
+    /// A system path.
+    Platform,
+
+    /// A path on a temporary file system.
+    Runtime,
+}
+
+/// Translate a Flatpak filesystem into a user, platform, or runtime file.
+fn filesystem(profile: &mut Profile, value: &str) -> bool {
+    let (path, mode) = match value.rsplit_once(':') {
+        Some((path, "ro")) => (path, FileMode::ReadOnly),
+        Some((path, "rw" | "create")) => (path, FileMode::ReadWrite),
+        _ => (value, FileMode::ReadWrite),
+    };
+
+    let (root, rest) = path.split_once('/').unwrap_or((path, ""));
+    let join = |base: &str| {
+        if rest.is_empty() {
+            base.to_owned()
+        } else {
+            format!("{base}/{rest}")
+        }
+    };
+
+    let (target, file) = match root {
+        "home" | "~" => (Target::User, join("~")),
+        "xdg-desktop" => (Target::User, join("Desktop")),
+        "xdg-documents" => (Target::User, join("Documents")),
+        "xdg-download" => (Target::User, join("Downloads")),
+        "xdg-music" => (Target::User, join("Music")),
+        "xdg-pictures" => (Target::User, join("Pictures")),
+        "xdg-public-share" => (Target::User, join("Public")),
+        "xdg-templates" => (Target::User, join("Templates")),
+        "xdg-videos" => (Target::User, join("Videos")),
+        "xdg-config" => (Target::User, join("$XDG_CONFIG_HOME")),
+        "xdg-data" => (Target::User, join("$XDG_DATA_HOME")),
+        "xdg-cache" => (Target::User, join("$XDG_CACHE_HOME")),
+        "xdg-run" if !rest.is_empty() => (Target::Runtime, join("$XDG_RUNTIME_DIR")),
+        "" if !rest.is_empty() => (Target::Platform, path.to_owned()),
+        _ => return false,
+    };
+
+    let files = profile.files.get_or_insert_default();
+    let list = match target {
+        Target::User => &mut files.user,
+        Target::Platform => &mut files.platform,
+        Target::Runtime => &mut files.runtime,
+    };
+    list.entry(mode).or_default().insert(file);
+    true
+}
+
+/// Convert a Flatpak manifest or metadata file into a profile. Returns the name of the
+/// profile, the profile, and the permissions that could not be translated.
+pub fn convert(path: &Path) -> Result<(String, Profile, Vec<String>)> {
+    let contents = fs::read_to_string(path)?;
+    let permissions = if contents.contains("[Application]") {
+        metadata(&contents)
+    } else {
+        manifest(&contents)
+    };
+
+    let name = permissions
+        .command
+        .as_deref()
+        .and_then(|command| Path::new(command).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .or_else(|| permissions.id.clone())
+        .ok_or_else(|| anyhow!("{} has no application ID or command", path.display()))?;
+
+    let mut profile = Profile {
+        id: permissions.id.clone(),
+        notes: permissions
+            .id
+            .as_ref()
+            .map(|id| format!("Converted from the Flatpak permissions of {id}.")),
+        ..Default::default()
+    };
+
+    let wayland = permissions.args.iter().any(|arg| arg == "--socket=wayland");
+    let mut untranslated = Vec::new();
+    for arg in &permissions.args {
+        let (flag, value) = arg
+            .trim_start_matches('-')
+            .split_once('=')
+            .unwrap_or((arg.trim_start_matches('-'), ""));
+
+        let translated = match (flag, value) {
+            ("socket", "wayland") => feature(&mut profile, "wayland"),
+            ("socket", "x11") => feature(&mut profile, "xorg"),
+
+            // Wayland is preferred when both are available.
+            ("socket", "fallback-x11") => wayland || feature(&mut profile, "xorg"),
+            ("socket", "pulseaudio") => feature(&mut profile, "pipewire"),
+            ("socket", "session-bus") => {
+                ipc(&mut profile).user_bus = Some(true);
+                true
+            }
+            ("socket", "system-bus") => {
+                ipc(&mut profile).system_bus = Some(true);
+                true
+            }
+            ("share", "network") => feature(&mut profile, "network"),
+            ("device", "dri") => feature(&mut profile, "dri"),
+            ("device", "kvm" | "shm" | "input") => {
+                profile.devices.insert(format!("/dev/{value}"));
+                true
+            }
+            ("device", "usb") => {
+                profile.devices.insert("/dev/bus/usb".to_owned());
+                true
+            }
+            ("filesystem", value) => filesystem(&mut profile, value),
+            ("talk-name", name) => {
+                ipc(&mut profile).talks.insert(name.to_owned());
+                true
+            }
+            ("own-name", name) => {
+                ipc(&mut profile).owns.insert(name.to_owned());
+                true
+            }
+            ("see-name", name) => {
+                ipc(&mut profile).sees.insert(name.to_owned());
+                true
+            }
+            ("system-talk-name", name) => {
+                ipc(&mut profile).system_talks.insert(name.to_owned());
+                true
+            }
+            ("system-see-name", name) => {
+                ipc(&mut profile).system_sees.insert(name.to_owned());
+                true
+            }
+            ("env", pair) => pair.split_once('=').is_some_and(|(key, value)| {
+                profile.environment.insert(key.to_owned(), value.to_owned());
+                true
+            }),
+            _ => false,
+        };
+
+        if !translated {
+            untranslated.push(arg.clone());
+        }
+    }
+
+    Ok((name, profile, untranslated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::profile::files::FileList;
+
+    /// Translate a filesystem, returning the lists of the resulting profile.
+    fn translate(value: &str) -> Option<(FileList, FileList, FileList)> {
+        let mut profile = Profile::default();
+        if !filesystem(&mut profile, value) {
+            return None;
+        }
+        let files = profile.files.unwrap_or_default();
+        Some((files.user, files.platform, files.runtime))
+    }
+
+    /// A list with a single file.
+    fn list(mode: FileMode, file: &str) -> FileList {
+        [(mode, [file.to_owned()].into_iter().collect())]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn translates_filesystems() {
+        let empty = FileList::default;
+        for (value, expected) in [
+            ("home", list(FileMode::ReadWrite, "~")),
+            ("~", list(FileMode::ReadWrite, "~")),
+            ("home/Projects:ro", list(FileMode::ReadOnly, "~/Projects")),
+            ("~/.config/app", list(FileMode::ReadWrite, "~/.config/app")),
+            ("xdg-download", list(FileMode::ReadWrite, "Downloads")),
+            ("xdg-music:ro", list(FileMode::ReadOnly, "Music")),
+            (
+                "xdg-pictures/Screenshots",
+                list(FileMode::ReadWrite, "Pictures/Screenshots"),
+            ),
+            (
+                "xdg-config/app:create",
+                list(FileMode::ReadWrite, "$XDG_CONFIG_HOME/app"),
+            ),
+        ] {
+            assert!(
+                translate(value) == Some((expected, empty(), empty())),
+                "{value} should be a user file"
+            );
+        }
+
+        assert!(
+            translate("/opt/app:ro")
+                == Some((empty(), list(FileMode::ReadOnly, "/opt/app"), empty()))
+        );
+        assert!(
+            translate("xdg-run/pipewire-0")
+                == Some((
+                    empty(),
+                    empty(),
+                    list(FileMode::ReadWrite, "$XDG_RUNTIME_DIR/pipewire-0")
+                ))
+        );
+
+        for value in ["host", "host-os", "xdg-run"] {
+            assert!(translate(value).is_none(), "{value} should be untranslated");
+        }
+    }
+
+    #[test]
+    fn reads_fields() {
+        let json = r#"{
+    "app-id": "org.example.App",
+    "command": "app",
+}"#;
+        assert_eq!(field(json, "app-id").as_deref(), Some("org.example.App"));
+        assert_eq!(field(json, "command").as_deref(), Some("app"));
+        assert_eq!(field(json, "runtime"), None);
+
+        let yaml = "id: org.example.App\ncommand: 'app'\n";
+        assert_eq!(field(yaml, "id").as_deref(), Some("org.example.App"));
+        assert_eq!(field(yaml, "command").as_deref(), Some("app"));
+    }
+
+    #[test]
+    fn reads_manifests() {
+        let json = r#"{
+    "app-id": "org.example.App",
+    "command": "app",
+    "finish-args": [
+        "--socket=wayland",
+        "--filesystem=xdg-download:ro",
+        "--talk-name=org.freedesktop.Notifications"
+    ],
+    "modules": []
+}"#;
+        let yaml = "app-id: org.example.App
+command: app
+finish-args:
+  - --socket=wayland
+  - --filesystem=xdg-download:ro
+  # Notifications
+  - --talk-name=org.freedesktop.Notifications
+modules:
+  - name: app
+";
+        for contents in [json, yaml] {
+            let permissions = manifest(contents);
+            assert_eq!(permissions.id.as_deref(), Some("org.example.App"));
+            assert_eq!(permissions.command.as_deref(), Some("app"));
+            assert_eq!(
+                permissions.args,
+                [
+                    "--socket=wayland",
+                    "--filesystem=xdg-download:ro",
+                    "--talk-name=org.freedesktop.Notifications"
+                ]
+            );
+        }
+    }
+}
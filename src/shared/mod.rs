@@ -1,14 +1,22 @@
 #![allow(clippy::missing_errors_doc)]
 
 pub mod config;
+pub mod delta;
 pub mod edit;
 pub mod env;
 pub mod feature;
 pub mod find;
+pub mod firejail;
+pub mod flatpak;
 pub mod package;
+pub mod permissions;
 pub mod profile;
+pub mod project;
+pub mod signature;
+pub mod snapshot;
 pub mod store;
 pub mod syscalls;
+pub mod vault;
 
 use crate::shared::{
     config::CONFIG_FILE,
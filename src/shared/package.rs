@@ -6,29 +6,32 @@ use crate::{
         run, run_vec,
     },
     fab::lib::ROOTS,
-    setup::setup,
+    setup::{Focused, setup},
     shared::{
         Map, Set,
+        delta::{self, Delta},
         env::{CACHE_DIR, DATA_HOME},
         find::{DirType, recursive_crawl},
         profile::Profile,
+        signature::{self, TRAILER_LENGTH, Trailer},
     },
     timer,
 };
 use anyhow::{Result, anyhow};
 use bilrost::{Message, OwnedMessage};
 use bstr::{BStr, BString};
-use clap::Parser;
+use clap::{Parser, ValueHint};
 use log::{error, info};
 use path_clean::clean;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use spawn::Spawner;
 use std::{
     env,
     ffi::OsStr,
     fs::{self, File},
-    io::{Read, Seek, SeekFrom},
-    os::unix::fs::{PermissionsExt, symlink},
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt, symlink},
     path::{Path, PathBuf},
     sync::LazyLock,
 };
@@ -54,6 +57,12 @@ pub static IS_PACKAGE: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
 // Package marker
 pub static PACKAGE_MARKER: [u8; 7] = *b"\0PKG\0\0\0";
 
+/// The largest window the payload is compressed with. Packages are mostly
+/// libraries and binaries that share a lot of content, so long distance matching
+/// across a large window pays off. 2^27 is the largest window zstd will decode
+/// without explicitly raising the limit.
+pub static WINDOW_LOG: u32 = 27;
+
 /// Command line arguments specifically for when running as a package
 #[derive(clap::Parser, Default)]
 #[command(name = "Antimony (Packaged)")]
@@ -81,6 +90,19 @@ pub struct Args {
     #[arg(long)]
     integrate: bool,
 
+    /// Run the package even if it is unsigned, or signed by an untrusted key.
+    #[arg(long)]
+    insecure: bool,
+
+    /// Apply a delta update to the package.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    apply: Option<PathBuf>,
+
+    /// The profile to run from a bundle. Defaults to the name the package was
+    /// invoked as, such as through a symlink, or else the main profile.
+    #[arg(long)]
+    profile: Option<String>,
+
     /// Run arguments
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
     pub passthrough: Option<Vec<String>>,
@@ -114,6 +136,54 @@ pub struct Package {
 
     /// Misc system files, like desktop files and icons.
     pub system_files: Map<String, BString>,
+
+    /// The version of Antimony that built the package.
+    pub builder: String,
+
+    /// The version of the package, if one was given.
+    pub version: Option<String>,
+
+    /// The SECCOMP allowlist exported from the builder's database.
+    pub allowlist: Allowlist,
+
+    /// Additional profiles bundled in the package, keyed by name. They share the
+    /// files, libraries and binaries of the package.
+    pub bundled: Map<String, Entry>,
+}
+
+/// A profile bundled alongside the main profile of a package.
+#[derive(Deserialize, Serialize, Default, Message)]
+pub struct Entry {
+    pub profile: Profile,
+    pub allowlist: Allowlist,
+}
+
+/// A precomputed SECCOMP policy. Syscalls are stored by name, as numbers differ
+/// between architectures.
+#[derive(Deserialize, Serialize, Default, Message, PartialEq, Eq, Clone)]
+pub struct Allowlist {
+    /// Syscalls used by the sandbox.
+    #[serde(default)]
+    pub syscalls: Vec<String>,
+
+    /// Syscalls only bubblewrap needs to set up the sandbox.
+    #[serde(default)]
+    pub bwrap: Vec<String>,
+}
+impl Allowlist {
+    /// Whether the allowlist contains anything.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.syscalls.is_empty()
+    }
+
+    /// Read the allowlist of an unpacked profile, if it has one.
+    #[must_use]
+    pub fn read(profile_path: &Path) -> Option<Self> {
+        fs::read_to_string(profile_path.with_extension("allowlist"))
+            .ok()
+            .and_then(|str| toml::from_str(&str).ok())
+    }
 }
 impl Package {
     pub fn add(&mut self, name: &str, dest: &str) -> Result<()> {
@@ -202,19 +272,141 @@ impl Package {
         Ok(())
     }
 
+    /// The fields of the package that hold file contents, by name.
+    #[must_use]
+    pub fn contents(&self) -> [(&'static str, &Map<String, BString>); 6] {
+        [
+            ("files", &self.files),
+            ("libraries", &self.libraries),
+            ("binaries", &self.binaries),
+            ("system_binaries", &self.system_binaries),
+            ("system_libraries", &self.system_libraries),
+            ("system_files", &self.system_files),
+        ]
+    }
+
+    /// The fields of the package that hold file contents, by name.
+    pub fn contents_mut(&mut self) -> [(&'static str, &mut Map<String, BString>); 6] {
+        [
+            ("files", &mut self.files),
+            ("libraries", &mut self.libraries),
+            ("binaries", &mut self.binaries),
+            ("system_binaries", &mut self.system_binaries),
+            ("system_libraries", &mut self.system_libraries),
+            ("system_files", &mut self.system_files),
+        ]
+    }
+
+    /// A SHA-256 hash of the entire package. Entries, and the profiles, are hashed in
+    /// a sorted form, so the digest is independent of the order they were added in.
+    #[must_use]
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        let mut update = |parts: [&[u8]; 3]| {
+            for part in parts {
+                hasher.update((part.len() as u64).to_le_bytes());
+                hasher.update(part);
+            }
+        };
+
+        for (field, entries) in self.contents() {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by_key(|(path, _)| *path);
+            for (path, content) in entries {
+                update([field.as_bytes(), path.as_bytes(), content.as_slice()]);
+            }
+        }
+
+        let mut symlinks: Vec<_> = self.symlinks.iter().collect();
+        symlinks.sort();
+        for (src, dest) in symlinks {
+            update([b"symlinks".as_slice(), src.as_bytes(), dest.as_bytes()]);
+        }
+
+        update([b"package".as_slice(), b"name", self.name.as_bytes()]);
+        update([b"package".as_slice(), b"builder", self.builder.as_bytes()]);
+        if let Some(version) = &self.version {
+            update([b"package".as_slice(), b"version", version.as_bytes()]);
+        }
+        update([
+            b"package".as_slice(),
+            b"profile",
+            canonical(&self.profile).as_bytes(),
+        ]);
+        update([
+            b"package".as_slice(),
+            b"allowlist",
+            canonical(&self.allowlist).as_bytes(),
+        ]);
+
+        let mut bundled: Vec<_> = self.bundled.iter().collect();
+        bundled.sort_by_key(|(name, _)| *name);
+        for (name, entry) in bundled {
+            update([
+                b"bundled".as_slice(),
+                name.as_bytes(),
+                canonical(entry).as_bytes(),
+            ]);
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// The names of every profile in the package, starting with the main profile.
+    pub fn entries(&self) -> impl Iterator<Item = &str> {
+        [self.name.as_str()]
+            .into_iter()
+            .chain(self.bundled.keys().map(String::as_str))
+    }
+
+    /// Write a profile, and its allowlist, into a directory.
+    fn write_profile(
+        dir: &Path,
+        name: &str,
+        profile: &Profile,
+        allowlist: &Allowlist,
+    ) -> Result<()> {
+        if name.contains('/') {
+            return Err(anyhow!("Invalid profile name in package: {name}"));
+        }
+
+        fs::write(dir.join(format!("{name}.toml")), toml::to_string(profile)?)?;
+
+        // The profile is found by its extension, so the allowlist must not be TOML-suffixed.
+        if !allowlist.is_empty() {
+            fs::write(
+                dir.join(format!("{name}.allowlist")),
+                toml::to_string(allowlist)?,
+            )?;
+        }
+        Ok(())
+    }
+
     /// Unpack the package
     pub fn unpack(&self, package_dir: &Path) -> Result<()> {
         fs::create_dir_all(package_dir)?;
-        fs::write(
-            package_dir.join(format!("{}.toml", self.name)),
-            toml::to_string(&self.profile)?,
-        )?;
+        Self::write_profile(package_dir, &self.name, &self.profile, &self.allowlist)?;
+
+        if !self.bundled.is_empty() {
+            let root = package_dir.join("profiles");
+            fs::create_dir(&root)?;
+            for (name, entry) in &self.bundled {
+                Self::write_profile(&root, name, &entry.profile, &entry.allowlist)?;
+            }
+        }
 
         let unpack = |root: &Path, path: &str, content: &BStr, executable: bool| -> Result<()> {
             let pkg_path = clean(root.join(path.strip_prefix('/').unwrap_or(path)));
 
             if !pkg_path.starts_with(package_dir) {
-                error!("File outside package directory: {}", pkg_path.display());
+                return Err(anyhow!(
+                    "File outside package directory: {}",
+                    pkg_path.display()
+                ));
             }
             if let Some(parent) = pkg_path.parent()
                 && !parent.exists()
@@ -283,8 +475,112 @@ impl Package {
     }
 }
 
-/// Grab the profile from a package directory.
-pub fn get_profile(path: &Path) -> Result<(Profile, PathBuf)> {
+/// A canonical form of a value to hash. Maps and sets encode in the order they iterate,
+/// which differs between a value and a decoded copy of it, so the value is written as TOML,
+/// whose tables are sorted, with every array sorted too.
+fn canonical<T: Serialize>(value: &T) -> String {
+    fn sort(value: &mut toml::Value) {
+        match value {
+            toml::Value::Array(array) => {
+                array.iter_mut().for_each(sort);
+                array.sort_by_cached_key(ToString::to_string);
+            }
+            toml::Value::Table(table) => table.values_mut().for_each(sort),
+            _ => {}
+        }
+    }
+
+    toml::Value::try_from(value).map_or_else(
+        |e| format!("{e}"),
+        |mut value| {
+            sort(&mut value);
+            value.to_string()
+        },
+    )
+}
+
+/// Compress a package, or delta, into its payload.
+pub fn encode<M: Message>(message: &M, level: i32) -> Result<Vec<u8>> {
+    let mut encoder = zstd::Encoder::new(Vec::new(), level)?;
+    encoder.long_distance_matching(true)?;
+    encoder.window_log(WINDOW_LOG)?;
+    encoder.include_checksum(true)?;
+    encoder.write_all(&message.encode_to_vec())?;
+    Ok(encoder.finish()?)
+}
+
+/// Assemble a package file from the Antimony binary and the payload, marking the binary
+/// as a package. A signature covers all of it, so that the binary that runs is signed too.
+#[must_use]
+pub fn assemble(binary: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut content = binary.to_vec();
+    if let Some(header) = content.get_mut(0x09..0x10) {
+        header.copy_from_slice(&PACKAGE_MARKER);
+    }
+    content.extend_from_slice(&PACKAGE_MARKER);
+    content.extend_from_slice(payload);
+    content
+}
+
+/// The signed contents of a package file, which must be everything before the trailer.
+#[must_use]
+pub fn signed<'a>(bytes: &'a [u8], trailer: &Trailer) -> Option<&'a [u8]> {
+    let signed = trailer.payload(bytes)?;
+    (signed.len() == bytes.len().checked_sub(TRAILER_LENGTH)?).then_some(signed)
+}
+
+/// Verify the signature of a package file against the trust store.
+///
+/// Unsigned packages, and those signed by an unknown key, are only permitted if
+/// `insecure` is set. A signature that does not match is always refused.
+pub fn verify(bytes: &[u8], trailer: Option<&Trailer>, insecure: bool) -> Result<()> {
+    if let Some(trailer) = trailer {
+        let Some(signed) = signed(bytes, trailer) else {
+            return Err(anyhow!("Signature Trailer Invalid! Corrupted Package!"));
+        };
+        signature::verify(signed, Some(trailer), insecure)?;
+    } else {
+        signature::verify(&[], None, insecure)?;
+    }
+    Ok(())
+}
+
+/// Extract the package from the contents of a package file.
+#[must_use]
+pub fn extract(bytes: &[u8], trailer: Option<&Trailer>) -> Option<Package> {
+    let bytes = match trailer {
+        Some(trailer) => signed(bytes, trailer)?,
+        None => bytes,
+    };
+
+    // We might hit multiple marker (At least one, as it's used in the ELF header), but
+    // the data shouldn't have it, so it should be the last one.
+    bytes
+        .windows(PACKAGE_MARKER.len())
+        .enumerate()
+        .find_map(|(position, window)| {
+            if window == PACKAGE_MARKER
+                && let Some(index) = position.checked_add(PACKAGE_MARKER.len())
+                && let Ok(bytes) = zstd::decode_all(&bytes[index..])
+                && let Ok(pkg) = Package::decode(bytes.as_slice())
+            {
+                Some(pkg)
+            } else {
+                None
+            }
+        })
+}
+
+/// Grab a profile from a package directory. If no entry is requested, or it is
+/// not bundled, the main profile of the package is used.
+pub fn get_profile(path: &Path, entry: Option<&str>) -> Result<(Profile, PathBuf)> {
+    if let Some(entry) = entry {
+        let bundled = path.join("profiles").join(format!("{entry}.toml"));
+        if bundled.exists() {
+            return Ok((toml::from_str(&fs::read_to_string(&bundled)?)?, bundled));
+        }
+    }
+
     let mut profile_path = None;
 
     for file in path.read_dir()?.filter_map(Result::ok) {
@@ -299,16 +595,69 @@ pub fn get_profile(path: &Path) -> Result<(Profile, PathBuf)> {
     let Some(profile_path) = profile_path else {
         return Err(anyhow!("Could not find package profile"));
     };
+    if let Some(entry) = entry
+        && profile_path.file_stem() != Some(OsStr::new(entry))
+    {
+        return Err(anyhow!("Package has no profile named {entry}"));
+    }
     Ok((
         toml::from_str(&fs::read_to_string(&profile_path)?)?,
         profile_path,
     ))
 }
 
-#[allow(
-    clippy::useless_let_if_seq,
-    reason = "It's not useless. Searching the binary may fail."
-)]
+/// Verify a package file, and get the digest that its unpacked copies and updates are
+/// keyed on, so that any change to the file, signed or not, unpacks it again.
+///
+/// Packages carry whole library trees, so reading, verifying and hashing one on every
+/// launch is expensive. Once a package is verified against the trust store, its digest is
+/// cached against the identity of the file (Its device, inode, size and modification time),
+/// and it is only read again when that changes. Packages that need `--insecure` are never
+/// cached, so they are checked, and need the flag, on every launch.
+fn source(file: &mut File, name: &OsStr, insecure: bool) -> Result<Vec<u8>> {
+    let metadata = file.metadata()?;
+    let identity = format!(
+        "{}:{}:{}:{}.{}\n",
+        metadata.dev(),
+        metadata.ino(),
+        metadata.size(),
+        metadata.mtime(),
+        metadata.mtime_nsec()
+    );
+    let cache = CACHE_DIR
+        .join("packages")
+        .join(format!(".{}.source", name.to_string_lossy()));
+    if let Ok(cached) = fs::read(&cache)
+        && let Some(source) = cached.strip_prefix(identity.as_bytes())
+    {
+        return Ok(source.to_vec());
+    }
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let trailer = Trailer::parse(&bytes);
+    verify(&bytes, trailer.as_ref(), insecure)?;
+
+    let source: Vec<u8> = Sha256::digest(&bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+        .into_bytes();
+
+    let trusted = trailer.as_ref().is_some_and(|trailer| {
+        signature::trusted()
+            .iter()
+            .any(|key| key.as_bytes() == &trailer.key)
+    });
+    if trusted {
+        if let Some(parent) = cache.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&cache, [identity.as_bytes(), &source].concat())?;
+    }
+    Ok(source)
+}
+
 #[allow(clippy::too_many_lines)]
 pub fn execute_package(current: &Path, mut file: File, name: &OsStr) -> Result<()> {
     let root_path = Path::new("/pkg");
@@ -329,48 +678,81 @@ pub fn execute_package(current: &Path, mut file: File, name: &OsStr) -> Result<(
                     root_path.to_string_lossy(),
                     &mut args,
                     false,
-                    Some((Package::default(), true))
+                    Some((
+                        Package {
+                            name: cli.profile.unwrap_or_default(),
+                            ..Default::default()
+                        },
+                        true
+                    ))
                 )
             )?;
             timer!("::run", run::run(info, &mut args))?;
             Ok(())
         }();
 
-        if let Err(e) = &result {
-            error!("{e}");
+        match result {
+            Err(e) if e.is::<Focused>() => {
+                info!("{e}");
+                Ok(())
+            }
+            Err(e) => {
+                error!("{e}");
+                Err(e)
+            }
+            Ok(()) => Ok(()),
         }
-        result
     } else {
-        let path = CACHE_DIR.join("packages").join(name);
-        if cli.refresh && path.exists() {
-            fs::remove_dir_all(&path)?;
+        let source = source(&mut file, name, cli.insecure)?;
+        let load = || -> Result<Package> {
+            let bytes = fs::read(current)?;
+            extract(&bytes, Trailer::parse(&bytes).as_ref())
+                .ok_or_else(|| anyhow!("Data Header Missing! Corrupted Package!"))
+        };
+
+        // Deltas apply on top of any update that has already been applied.
+        if let Some(delta) = &cli.apply {
+            let base = match delta::updated(name, &source) {
+                Some(dir) => delta::installed(&dir)?,
+                None => load()?,
+            };
+            let package = Delta::read(delta, cli.insecure)?.apply(base)?;
+            let dir = delta::install(name, &source, &package)?;
+            println!("Updated {} at {}", package.name, dir.display());
+            return Ok(());
         }
 
-        if !path.exists() {
-            let mut package = None;
-            file.rewind()?;
-            let mut bytes = Vec::new();
-            file.read_to_end(&mut bytes)?;
-
-            // We might hit multiple marker (At least one, as it's used in the ELF header), but
-            // the data shouldn't have it, so it should be the last one.
-            for (position, window) in bytes.windows(PACKAGE_MARKER.len()).enumerate() {
-                if window == PACKAGE_MARKER
-                    && let Some(index) = position.checked_add(PACKAGE_MARKER.len())
-                    && let Ok(bytes) = zstd::decode_all(&bytes[index..])
-                    && let Ok(pkg) = Package::decode(bytes.as_slice())
-                {
-                    package = Some(pkg);
-                    break;
-                }
+        let path = if let Some(dir) = delta::updated(name, &source) {
+            if cli.refresh {
+                delta::install(name, &source, &delta::installed(&dir)?)?
+            } else {
+                dir
+            }
+        } else {
+            let path = CACHE_DIR.join("packages").join(name);
+
+            // The digest of the unpacked package is kept alongside it. If the package
+            // has changed underneath us, it needs to be unpacked again.
+            let digest = path.join(".digest");
+            let stale = path.exists() && fs::read(&digest).ok().as_ref() != Some(&source);
+            if (cli.refresh || stale) && path.exists() {
+                fs::remove_dir_all(&path)?;
             }
 
-            if let Some(package) = package {
-                package.unpack(&path)?;
-            } else {
-                return Err(anyhow!("Data Header Missing! Corrupted Package!"));
+            if !path.exists() {
+                load()?.unpack(&path)?;
+                fs::write(&digest, &source)?;
             }
-        }
+            path
+        };
+
+        // If the package was invoked through a symlink named after one of its
+        // profiles, that profile is the entrypoint.
+        let entry = cli.profile.is_none().then(env::args).and_then(|mut args| {
+            let invoked = PathBuf::from(args.next()?);
+            let invoked = invoked.file_name()?.to_string_lossy().into_owned();
+            get_profile(&path, Some(&invoked)).ok().map(|_| invoked)
+        });
 
         if cli.integrate {
             let desktop: Set<_> = path
@@ -389,7 +771,7 @@ pub fn execute_package(current: &Path, mut file: File, name: &OsStr) -> Result<(
             if !pkg_path.exists() {
                 fs::create_dir_all(&pkg_path)?;
             }
-            let (mut profile, _) = get_profile(&path)?;
+            let (mut profile, _) = get_profile(&path, None)?;
             let cmd = cli
                 .passthrough
                 .map_or_else(integrate::Args::default, |passthrough| {
@@ -527,10 +909,86 @@ pub fn execute_package(current: &Path, mut file: File, name: &OsStr) -> Result<(
 
         let current_str = current.to_string_lossy();
         #[rustfmt::skip]
-        handle.args([
+        handle.args_i([
             "--ro-bind", &current_str, "/pkg.sb",
             "--", "/pkg.sb"
-        ]).args(env::args().skip(1)).spawn()?.wait()?;
+        ]);
+        if let Some(entry) = entry {
+            handle.args_i(["--profile", &entry]);
+        }
+        handle.args(env::args().skip(1)).spawn()?.wait()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Package, assemble, verify};
+    use crate::shared::{profile::Profile, signature::Trailer};
+    use bilrost::{Message, OwnedMessage};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    /// A package over a stand-in binary, signed by a key outside the trust store.
+    fn signed_package() -> Vec<u8> {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut content = assemble(&[0x7f; 64], b"payload");
+        let trailer = Trailer {
+            length: content.len() as u64,
+            key: key.verifying_key().to_bytes(),
+            signature: key.sign(&content).to_bytes(),
+        };
+        content.extend(trailer.to_bytes());
+        content
+    }
+
+    #[test]
+    fn tampered_binary() {
+        let mut package = signed_package();
+        assert!(verify(&package, Trailer::parse(&package).as_ref(), true).is_ok());
+
+        // The binary is what runs, so it is covered by the signature.
+        if let Some(byte) = package.get_mut(0x20) {
+            *byte ^= 0xff;
+        }
+        assert!(verify(&package, Trailer::parse(&package).as_ref(), true).is_err());
+    }
+
+    #[test]
+    fn digest_decoded() {
+        let mut profile = Profile::default();
+        for index in 0..64 {
+            profile
+                .environment
+                .insert(format!("KEY_{index}"), index.to_string());
+            profile.binaries.insert(format!("/usr/bin/{index}"));
+        }
+
+        // Maps that have shrunk iterate differently to a freshly decoded copy.
+        for index in 8..64 {
+            profile.environment.remove(&format!("KEY_{index}"));
+            profile.binaries.remove(&format!("/usr/bin/{index}"));
+        }
+
+        let mut package = Package {
+            name: "test".to_owned(),
+            profile: profile.clone(),
+            ..Default::default()
+        };
+        for index in 0..8 {
+            package
+                .files
+                .insert(format!("/file/{index}"), index.to_string().into());
+        }
+        package.bundled.insert(
+            "bundled".to_owned(),
+            Entry {
+                profile,
+                ..Default::default()
+            },
+        );
+
+        let decoded =
+            Package::decode(package.encode_to_vec().as_slice()).expect("Failed to decode");
+        assert_eq!(package.digest(), decoded.digest());
+    }
+}
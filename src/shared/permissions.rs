@@ -0,0 +1,149 @@
+//! Portal permissions, kept by xdg-desktop-portal in its permission store.
+//!
+//! Portals such as the Camera ask the user the first time an application uses
+//! them, and remember the answer in the store under the application's ID. Writing
+//! the answer ahead of time spares the prompt, or denies the portal outright.
+
+use crate::shared::{Map, profile::ipc::Portal};
+use anyhow::{Result, anyhow};
+use dbus::blocking::{LocalConnection, Proxy};
+use std::time::Duration;
+
+/// The interface of the permission store.
+static STORE: &str = "org.freedesktop.impl.portal.PermissionStore";
+
+/// The portals that keep their grants in the permission store.
+pub static PORTALS: [Portal; 5] = [
+    Portal::Background,
+    Portal::Camera,
+    Portal::Location,
+    Portal::Notifications,
+    Portal::Screenshot,
+];
+
+/// The table and id a portal stores its grant under, if it does.
+const fn entry(portal: &Portal) -> Option<(&'static str, &'static str)> {
+    match portal {
+        Portal::Background => Some(("background", "background")),
+        Portal::Camera => Some(("devices", "camera")),
+        Portal::Location => Some(("location", "location")),
+        Portal::Notifications => Some(("notifications", "notification")),
+        Portal::Screenshot => Some(("screenshot", "screenshot")),
+        _ => None,
+    }
+}
+
+/// The values a portal accepts as its permission.
+const fn values(portal: &Portal) -> &'static [&'static str] {
+    match portal {
+        Portal::Location => &["NONE", "COUNTRY", "CITY", "NEIGHBORHOOD", "STREET", "EXACT"],
+        _ => &["yes", "no", "ask"],
+    }
+}
+
+/// Check that every permission is for a portal that stores one, and is a value it understands.
+pub fn validate(permissions: &Map<Portal, String>) -> Result<()> {
+    for (portal, permission) in permissions {
+        if entry(portal).is_none() {
+            return Err(anyhow!("{portal} does not store permissions"));
+        }
+        let values = values(portal);
+        if !values.contains(&permission.as_str()) {
+            return Err(anyhow!(
+                "Invalid permission for {portal}: {permission}. Expected one of: {}",
+                values.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Run a closure with a proxy to the permission store.
+fn with_store<T>(f: impl FnOnce(&Proxy<'_, &LocalConnection>) -> Result<T>) -> Result<T> {
+    let connection = LocalConnection::new_session()?;
+    let proxy = connection.with_proxy(
+        STORE,
+        "/org/freedesktop/impl/portal/PermissionStore",
+        Duration::from_secs(5),
+    );
+    f(&proxy)
+}
+
+/// Write the permissions of an application into the store.
+pub fn apply(id: &str, permissions: &Map<Portal, String>) -> Result<()> {
+    validate(permissions)?;
+    with_store(|proxy| {
+        for (portal, permission) in permissions {
+            let (table, entry) =
+                entry(portal).ok_or_else(|| anyhow!("{portal} does not store permissions"))?;
+
+            // Location records the accuracy alongside when it was last used.
+            let value = if *portal == Portal::Location {
+                vec![permission.clone(), "0".to_owned()]
+            } else {
+                vec![permission.clone()]
+            };
+
+            proxy.method_call::<(), _, _, _>(
+                STORE,
+                "SetPermission",
+                (table, true, entry, id, value),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// Get the permissions the store holds for an application.
+pub fn get(id: &str) -> Result<Vec<(Portal, Option<Vec<String>>)>> {
+    with_store(|proxy| {
+        Ok(PORTALS
+            .iter()
+            .filter_map(|portal| entry(portal).map(|entry| (portal, entry)))
+            .map(|(portal, (table, entry))| {
+                // The store errors if there is no entry at all.
+                let permission = proxy
+                    .method_call::<(Vec<String>,), _, _, _>(
+                        STORE,
+                        "GetPermission",
+                        (table, entry, id),
+                    )
+                    .ok()
+                    .map(|(permission,)| permission)
+                    .filter(|permission| !permission.is_empty());
+                (portal.clone(), permission)
+            })
+            .collect())
+    })
+}
+
+/// Remove the permission of an application, such that the portal asks again.
+pub fn revoke(id: &str, portal: &Portal) -> Result<()> {
+    let (table, entry) =
+        entry(portal).ok_or_else(|| anyhow!("{portal} does not store permissions"))?;
+    with_store(|proxy| {
+        proxy.method_call::<(), _, _, _>(STORE, "DeletePermission", (table, entry, id))?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::shared::{Map, profile::ipc::Portal};
+
+    #[test]
+    fn validates() {
+        let mut permissions = Map::default();
+        permissions.insert(Portal::Camera, "yes".to_owned());
+        permissions.insert(Portal::Location, "CITY".to_owned());
+        assert!(validate(&permissions).is_ok());
+
+        permissions.insert(Portal::Camera, "yse".to_owned());
+        assert!(validate(&permissions).is_err());
+
+        permissions.insert(Portal::Camera, "yes".to_owned());
+        permissions.insert(Portal::Location, "yes".to_owned());
+        assert!(validate(&permissions).is_err());
+    }
+}
@@ -1,8 +1,11 @@
-use crate::{cli, shared::env::DATA_HOME};
+use crate::{
+    cli,
+    shared::{Set, env::DATA_HOME, snapshot::SNAPSHOT_DIR},
+};
 use bilrost::{Enumeration, Message};
 use clap::ValueEnum;
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde::{Deserialize, Deserializer, Serialize, de};
+use std::{fs, path::PathBuf};
 
 /// Sandboxes can define home folders in the user's home at ~/.local/share/antimony
 /// for persistent configurations and caches.
@@ -23,6 +26,27 @@ pub struct Home {
 
     /// What to do if the home is locked
     pub lock_policy: Option<HomeLockPolicy>,
+
+    /// Whether to snapshot the home each time the profile is launched.
+    pub snapshot_on_launch: Option<bool>,
+
+    /// How many snapshots taken on launch to keep.
+    pub snapshot_retention: Option<u32>,
+
+    /// Where to get the passphrase of an encrypted home.
+    pub secret: Option<HomeSecret>,
+
+    /// Paths within the home to persist. If set, the home is a fresh tmpfs on each
+    /// launch, and only these paths are mounted read/write from the home folder.
+    #[serde(default, skip_serializing_if = "Set::is_empty")]
+    pub persist: Set<String>,
+
+    /// A template directory to seed the fresh home with on each launch.
+    pub seed: Option<String>,
+
+    /// Use a separate account of the home, stored alongside the default one.
+    #[serde(default, deserialize_with = "deserialize_account")]
+    pub account: Option<String>,
 }
 impl Home {
     pub fn merge(&mut self, home: Self) {
@@ -41,25 +65,114 @@ impl Home {
         if self.lock_policy.is_none() {
             self.lock_policy = home.lock_policy;
         }
+        if self.snapshot_on_launch.is_none() {
+            self.snapshot_on_launch = home.snapshot_on_launch;
+        }
+        if self.snapshot_retention.is_none() {
+            self.snapshot_retention = home.snapshot_retention;
+        }
+        if self.secret.is_none() {
+            self.secret = home.secret;
+        }
+        if self.seed.is_none() {
+            self.seed = home.seed;
+        }
+        if self.account.is_none() {
+            self.account = home.account;
+        }
+        self.persist.extend(home.persist);
     }
 
-    pub const fn from_args(args: &mut cli::run::Args) -> Self {
+    pub fn from_args(args: &mut cli::run::Args) -> Self {
         Self {
             name: args.home_name.take(),
             policy: args.home_policy.take(),
             path: args.home_path.take(),
             lock: args.home_lock.take(),
             lock_policy: args.home_lock_policy.take(),
+            snapshot_on_launch: None,
+            snapshot_retention: None,
+            secret: None,
+            persist: Set::default(),
+            seed: None,
+            account: args.account.take(),
         }
     }
 
+    /// The name of the home folder, including the account: `name@account`.
+    pub fn key(&self, name: &str) -> String {
+        let name = self.name.as_deref().unwrap_or(name);
+        self.account
+            .as_ref()
+            .map_or_else(|| name.to_owned(), |account| format!("{name}@{account}"))
+    }
+
     pub fn path(&self, name: &str) -> PathBuf {
-        DATA_HOME
+        DATA_HOME.join("antimony").join(self.key(name))
+    }
+
+    /// Where snapshots of the home are stored.
+    pub fn snapshots(&self, name: &str) -> PathBuf {
+        SNAPSHOT_DIR.join(self.key(name))
+    }
+
+    /// The accounts of the home, sorted.
+    pub fn accounts(&self, name: &str) -> Vec<String> {
+        let base = DATA_HOME
             .join("antimony")
-            .join(self.name.as_ref().map_or(name, |name| name))
+            .join(self.name.as_deref().unwrap_or(name));
+        let (Some(parent), Some(name)) = (base.parent(), base.file_name()) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}@", name.to_string_lossy());
+
+        let mut accounts: Vec<String> = fs::read_dir(parent)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.path().is_dir())
+                    .filter_map(|entry| {
+                        entry
+                            .file_name()
+                            .to_string_lossy()
+                            .strip_prefix(&prefix)
+                            .map(ToOwned::to_owned)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        accounts.sort();
+        accounts
+    }
+}
+
+/// Validate the name of an account.
+///
+/// ## Errors
+/// If the name is empty, or contains anything but alphanumerics, dashes and underscores.
+pub fn parse_account(account: &str) -> Result<String, String> {
+    if !account.is_empty()
+        && account
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(account.to_owned())
+    } else {
+        Err(format!(
+            "Invalid account {account}: only alphanumerics, dashes and underscores are allowed"
+        ))
     }
 }
 
+/// Validate an account read from a profile, as it becomes part of the home's path.
+fn deserialize_account<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|account| parse_account(&account).map_err(de::Error::custom))
+        .transpose()
+}
+
 /// The Home Policy being set creates a persistent home folder for the profile.
 #[derive(
     Deserialize, Serialize, PartialEq, Eq, Clone, Copy, ValueEnum, Default, Enumeration,
@@ -83,6 +196,24 @@ pub enum HomePolicy {
     /// it can be shared by multiple instances, even if that application doesn't typically
     /// support multiple instances (Zed, Chromium, etc).
     Overlay = 3,
+
+    /// The Home Folder is encrypted with gocryptfs, and is unlocked and mounted read/write
+    /// when the profile is launched. It is unmounted once the last instance closes.
+    Encrypted = 4,
+}
+
+/// Where the passphrase of an encrypted home comes from.
+#[derive(
+    Deserialize, Serialize, PartialEq, Eq, Clone, Copy, ValueEnum, Default, Enumeration,
+)]
+#[serde(deny_unknown_fields)]
+pub enum HomeSecret {
+    /// Derive the passphrase from the secret the Secret Portal provides Antimony.
+    #[default]
+    Portal = 0,
+
+    /// Prompt for the passphrase, on the terminal or with kdialog.
+    Prompt = 1,
 }
 
 /// What to do when the profile is locked.
@@ -1,9 +1,24 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
-use crate::{cli, shared::Set};
+use crate::{
+    cli,
+    shared::{Map, Set},
+};
 use bilrost::{Enumeration, Message};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// An error for malformed D-Bus rules.
+#[derive(Debug, Error)]
+#[error("Invalid D-Bus rule {rule}: {reason}")]
+pub struct RuleError {
+    /// The rule, as written.
+    rule: String,
+
+    /// What is wrong with it.
+    reason: &'static str,
+}
 
 /// IPC mediated via xdg-dbus-proxy.
 #[derive(Default, Deserialize, Serialize, PartialEq, Eq, Clone, Message)]
@@ -18,6 +33,10 @@ pub struct Ipc {
     /// Provide the user bus directly. xdg-dbus-proxy is not run. Defaults to false.
     pub user_bus: Option<bool>,
 
+    /// Provide the accessibility bus, through a dedicated proxy that only lets
+    /// the sandbox register itself. Defaults to false.
+    pub accessibility: Option<bool>,
+
     /// Apply SOF and SECCOMP rules to the proxy sandbox. By default, the proxy
     /// is exempt from such security measures as there is some implicit trust
     /// on the `xdg-dbus-proxy` and separation between its sandbox and the profile.
@@ -30,6 +49,11 @@ pub struct Ipc {
     #[serde(skip_serializing_if = "Set::is_empty")]
     pub portals: Set<Portal>,
 
+    /// Permissions written into the permission store for portals that would otherwise
+    /// prompt, such as `Camera = "yes"`, or `Location = "EXACT"`.
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub portal_permissions: Map<Portal, String>,
+
     /// Busses that the sandbox can see, but not interact with.
     #[serde(skip_serializing_if = "Set::is_empty")]
     pub sees: Set<String>,
@@ -42,9 +66,26 @@ pub struct Ipc {
     #[serde(skip_serializing_if = "Set::is_empty")]
     pub owns: Set<String>,
 
-    /// Call semantics.
+    /// Methods the sandbox can call.
+    #[serde(skip_serializing_if = "Set::is_empty")]
+    pub calls: Set<Rule>,
+
+    /// Signals the sandbox can receive from names it can only see.
     #[serde(skip_serializing_if = "Set::is_empty")]
-    pub calls: Set<String>,
+    pub broadcasts: Set<Rule>,
+
+    /// System busses that the sandbox can see, but not interact with. Setting any
+    /// of the system lists runs a second proxy for the system bus.
+    #[serde(skip_serializing_if = "Set::is_empty")]
+    pub system_sees: Set<String>,
+
+    /// System busses the sandbox can talk over.
+    #[serde(skip_serializing_if = "Set::is_empty")]
+    pub system_talks: Set<String>,
+
+    /// Methods the sandbox can call on the system bus.
+    #[serde(skip_serializing_if = "Set::is_empty")]
+    pub system_calls: Set<Rule>,
 }
 impl Ipc {
     /// Merge two IPC sets together.
@@ -59,15 +100,34 @@ impl Ipc {
         if self.user_bus.is_none() {
             self.user_bus = ipc.user_bus;
         }
+        if self.accessibility.is_none() {
+            self.accessibility = ipc.accessibility;
+        }
         if self.harden.is_none() {
             self.harden = ipc.harden;
         }
 
         self.portals.extend(ipc.portals);
+        for (portal, permission) in ipc.portal_permissions {
+            self.portal_permissions.entry(portal).or_insert(permission);
+        }
         self.sees.extend(ipc.sees);
         self.talks.extend(ipc.talks);
         self.owns.extend(ipc.owns);
         self.calls.extend(ipc.calls);
+        self.broadcasts.extend(ipc.broadcasts);
+        self.system_sees.extend(ipc.system_sees);
+        self.system_talks.extend(ipc.system_talks);
+        self.system_calls.extend(ipc.system_calls);
+    }
+
+    /// Whether the system bus should be proxied, rather than provided directly.
+    #[must_use]
+    pub fn system_proxy(&self) -> bool {
+        !self.system_bus.unwrap_or(false)
+            && (!self.system_sees.is_empty()
+                || !self.system_talks.is_empty()
+                || !self.system_calls.is_empty())
     }
 
     /// Construct an IPC set from the command line.
@@ -89,6 +149,18 @@ impl Ipc {
         if let Some(call) = args.calls.take() {
             ipc.get_or_insert_default().calls = call.into_iter().collect();
         }
+        if let Some(broadcast) = args.broadcasts.take() {
+            ipc.get_or_insert_default().broadcasts = broadcast.into_iter().collect();
+        }
+        if let Some(see) = args.system_sees.take() {
+            ipc.get_or_insert_default().system_sees = see.into_iter().collect();
+        }
+        if let Some(talk) = args.system_talks.take() {
+            ipc.get_or_insert_default().system_talks = talk.into_iter().collect();
+        }
+        if let Some(call) = args.system_calls.take() {
+            ipc.get_or_insert_default().system_calls = call.into_iter().collect();
+        }
 
         if args.user_bus {
             ipc.get_or_insert_default().user_bus = Some(true);
@@ -107,6 +179,148 @@ impl Ipc {
     }
 }
 
+/// A rule matching messages on a bus, written as NAME=INTERFACE.MEMBER@PATH, where
+/// everything following the name is optional. The name may end in `.*` to match those
+/// beneath it, the interface may be followed by `.*` or omitted for any member, and
+/// the path may end in `/*` to match the objects beneath it.
+#[derive(Hash, Eq, PartialEq, Deserialize, Serialize, Clone, Debug, Message)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rule {
+    /// The bus name.
+    pub name: String,
+
+    /// The interface, or any if not set.
+    pub interface: Option<String>,
+
+    /// The method or signal, or any if not set.
+    pub member: Option<String>,
+
+    /// The object path, or any if not set.
+    pub path: Option<String>,
+}
+impl Rule {
+    /// Whether a string is a valid dot-separated D-Bus name, such as a bus name or
+    /// interface. Bus names may also have dashes.
+    fn valid_name(name: &str, dashes: bool) -> bool {
+        let name = name.strip_suffix(".*").unwrap_or(name);
+        name.len() <= 255
+            && name.split('.').count() >= 2
+            && name
+                .split('.')
+                .all(|element| Self::valid_element(element, dashes))
+    }
+
+    /// Whether a string is a valid element of a name, member, or path.
+    fn valid_element(element: &str, dashes: bool) -> bool {
+        !element.is_empty()
+            && !element.starts_with(|c: char| c.is_ascii_digit())
+            && element
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || (dashes && c == '-'))
+    }
+
+    /// Whether a string is a valid object path, optionally ending in a wildcard.
+    fn valid_path(path: &str) -> bool {
+        let path = path.strip_suffix("/*").unwrap_or(path);
+        path == "/"
+            || path.is_empty()
+            || path.strip_prefix('/').is_some_and(|path| {
+                path.split('/').all(|element| {
+                    !element.is_empty()
+                        && element
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                })
+            })
+    }
+}
+impl FromStr for Rule {
+    type Err = RuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let error = |reason| RuleError {
+            rule: rule.to_owned(),
+            reason,
+        };
+
+        let (name, filter) = rule.split_once('=').unwrap_or((rule, ""));
+        if !Self::valid_name(name, true) {
+            return Err(error(
+                "the bus name must have at least two elements separated by dots",
+            ));
+        }
+
+        let (method, path) = match filter.split_once('@') {
+            Some((method, path)) => (method, Some(path)),
+            None => (filter, None),
+        };
+
+        let path = match path {
+            Some(path) if !path.starts_with('/') || !Self::valid_path(path) => {
+                return Err(error(
+                    "the path must be absolute, such as /org/example/Object",
+                ));
+            }
+            path => path.map(ToOwned::to_owned),
+        };
+
+        let (interface, member) = match method {
+            "" | "*" => (None, None),
+            method => {
+                let (interface, member) = method
+                    .rsplit_once('.')
+                    .ok_or_else(|| error("the method must be INTERFACE.MEMBER, or INTERFACE.*"))?;
+                if !Self::valid_name(interface, false) {
+                    return Err(error(
+                        "the interface must have at least two elements separated by dots",
+                    ));
+                }
+                let member = if member == "*" {
+                    None
+                } else if Self::valid_element(member, false) {
+                    Some(member.to_owned())
+                } else {
+                    return Err(error("the member must be a single name, or *"));
+                };
+                (Some(interface.to_owned()), member)
+            }
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            interface,
+            member,
+            path,
+        })
+    }
+}
+impl TryFrom<String> for Rule {
+    type Error = RuleError;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+impl From<Rule> for String {
+    fn from(rule: Rule) -> Self {
+        rule.to_string()
+    }
+}
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        if let Some(interface) = &self.interface {
+            write!(f, "{interface}.{}", self.member.as_deref().unwrap_or("*"))?;
+        } else {
+            write!(f, "*")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "@{path}")?;
+        }
+        Ok(())
+    }
+}
+
 /// A non-exhaustive list of Portals. Some may not be
 /// implemented for certain Desktop Environments.
 /// Not all applications use portals, even if they
@@ -159,3 +373,34 @@ impl fmt::Display for Portal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rules() {
+        for rule in [
+            "org.example.App=org.example.App.Open@/org/example/App",
+            "org.example.App=org.example.App.*@/org/example/*",
+            "org.example.*=*@/",
+        ] {
+            assert_eq!(
+                rule.parse::<Rule>()
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .ok()
+                    .as_deref(),
+                Some(rule)
+            );
+        }
+        for rule in [
+            "example",
+            "org.example.App=Open",
+            "org.example.App=org.example.App.Open@relative",
+            "org.example.App=org.example.App.Op-en",
+        ] {
+            assert!(rule.parse::<Rule>().is_err(), "{rule} should be rejected");
+        }
+    }
+}
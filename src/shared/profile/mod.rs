@@ -24,6 +24,7 @@ use crate::{
         env::HOME,
         feature,
         profile::lib::Libraries,
+        project,
         store::{self, CACHE_STORE, Object, USER_STORE},
     },
 };
@@ -289,6 +290,9 @@ impl Profile {
         if let Some(policy) = args.home_policy.take() {
             profile.home.get_or_insert_default().policy = Some(policy);
         }
+        if let Some(account) = args.account.take() {
+            profile.home.get_or_insert_default().account = Some(account);
+        }
         if let Some(ignored) = args.ignored_exit_codes.take() {
             profile.ignored_exit_codes.extend(ignored);
         }
@@ -325,6 +329,13 @@ impl Profile {
 
         if let Some(args) = args {
             if !CONFIG_FILE.system_mode() {
+                // The project sits above the profile, and the command line above both.
+                // An exported script describes the profile itself, wherever it's exported from.
+                if !args.record
+                    && let Some(project) = project::load()?
+                {
+                    profile = profile.base(project)?;
+                }
                 let cmd_profile = Self::from_args(args)?;
                 profile = profile.base(cmd_profile)?;
             }
@@ -0,0 +1,149 @@
+//! Project-local overrides, read from `.antimony.toml` in the working directory or
+//! its ancestors, and layered over the profile.
+//!
+//! A fragment lives in the project, and so in anything cloned from elsewhere. It
+//! is only used once the user approves it, which records the fragment's hash in
+//! `$AT_HOME/projects`. Any change to the fragment invalidates the approval.
+
+use crate::shared::{
+    env::{AT_HOME, PWD},
+    profile::{Error, Profile},
+};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use user::{USER, as_effective};
+
+/// The name of the fragment.
+pub static FRAGMENT: &str = ".antimony.toml";
+
+/// Where approvals are recorded.
+static APPROVALS: LazyLock<PathBuf> = LazyLock::new(|| AT_HOME.join("projects"));
+
+/// Hash bytes into a hex string.
+fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The record of a fragment's approval in `approvals`, unique to the user and where it lives.
+fn record(approvals: &Path, fragment: &Path) -> PathBuf {
+    approvals.join(digest(
+        format!("{}:{}", USER.real.as_raw(), fragment.display()).as_bytes(),
+    ))
+}
+
+/// Find the closest fragment, searching up from the working directory.
+#[must_use]
+pub fn find() -> Option<PathBuf> {
+    PWD.ancestors()
+        .map(|dir| dir.join(FRAGMENT))
+        .find(|path| path.is_file())
+        .and_then(|path| fs::canonicalize(path).ok())
+}
+
+/// Whether the fragment, as it currently is, has been approved.
+#[must_use]
+pub fn approved(fragment: &Path, contents: &str) -> bool {
+    approved_in(&APPROVALS, fragment, contents)
+}
+
+/// Whether the fragment has been approved in `approvals`.
+fn approved_in(approvals: &Path, fragment: &Path, contents: &str) -> bool {
+    fs::read_to_string(record(approvals, fragment))
+        .is_ok_and(|hash| hash == digest(contents.as_bytes()))
+}
+
+/// Approve the fragment as it currently is.
+pub fn approve(fragment: &Path, contents: &str) -> Result<(), Error> {
+    approve_in(&APPROVALS, fragment, contents)
+}
+
+/// Approve the fragment in `approvals`.
+fn approve_in(approvals: &Path, fragment: &Path, contents: &str) -> Result<(), Error> {
+    let record = record(approvals, fragment);
+    let hash = digest(contents.as_bytes());
+    as_effective!(Result<(), io::Error>, {
+        fs::create_dir_all(approvals)?;
+        fs::write(&record, hash)
+    })??;
+    Ok(())
+}
+
+/// Revoke the approval of the fragment.
+pub fn revoke(fragment: &Path) -> Result<bool, Error> {
+    let record = record(&APPROVALS, fragment);
+    if !record.exists() {
+        return Ok(false);
+    }
+    as_effective!(fs::remove_file(&record))??;
+    Ok(true)
+}
+
+/// Load the closest fragment, if there is one and it is approved.
+pub fn load() -> Result<Option<Profile>, Error> {
+    find().map_or(Ok(None), |fragment| read(&APPROVALS, &fragment))
+}
+
+/// Read a fragment, if it is approved in `approvals`.
+fn read(approvals: &Path, fragment: &Path) -> Result<Option<Profile>, Error> {
+    let contents = fs::read_to_string(fragment)?;
+    if !approved_in(approvals, fragment, &contents) {
+        warn!(
+            "Ignoring {}, as it has not been approved. Review it, then run `antimony trust`",
+            fragment.display()
+        );
+        return Ok(None);
+    }
+    Ok(Some(toml::from_str(&contents)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FRAGMENT, approve_in, read};
+    use std::fs;
+
+    #[test]
+    fn unapproved() {
+        let dir = temp::Builder::new()
+            .create::<temp::Directory>()
+            .expect("Failed to create temporary directory");
+        let fragment = dir.full().join(FRAGMENT);
+        fs::write(&fragment, "path = \"/usr/bin/true\"").expect("Failed to write fragment");
+        assert!(
+            read(&dir.full().join("projects"), &fragment)
+                .expect("Failed to read fragment")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn changed() {
+        let dir = temp::Builder::new()
+            .create::<temp::Directory>()
+            .expect("Failed to create temporary directory");
+        let approvals = dir.full().join("projects");
+        let fragment = dir.full().join(FRAGMENT);
+        let contents = "path = \"/usr/bin/true\"";
+        fs::write(&fragment, contents).expect("Failed to write fragment");
+        approve_in(&approvals, &fragment, contents).expect("Failed to approve fragment");
+        assert!(
+            read(&approvals, &fragment)
+                .expect("Failed to read fragment")
+                .is_some()
+        );
+
+        fs::write(&fragment, "path = \"/usr/bin/false\"").expect("Failed to write fragment");
+        assert!(
+            read(&approvals, &fragment)
+                .expect("Failed to read fragment")
+                .is_none()
+        );
+    }
+}
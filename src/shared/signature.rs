@@ -0,0 +1,197 @@
+//! Ed25519 signing and verification of package payloads.
+//!
+//! A signed file appends a trailer containing the length of what was signed, the public
+//! key of the signer, and the signature itself, followed by `SIGNATURE_MARKER`. Packages
+//! sign everything before the trailer, including the executable that runs them, while
+//! deltas sign their payload. Keys are PEM encoded, such as those created by
+//! `openssl genpkey -algorithm ed25519`. Public keys that are trusted to sign packages
+//! are placed in `$AT_HOME/keys`, which only privileged users can modify.
+
+use crate::shared::env::AT_HOME;
+use ed25519_dalek::{
+    PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH, Signature, Signer, SigningKey, VerifyingKey,
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+};
+use log::warn;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use thiserror::Error;
+
+/// Marks the end of a signed package.
+pub static SIGNATURE_MARKER: [u8; 8] = *b"\0SIG\0\0\0\0";
+
+/// The size of the trailer: Length + Key + Signature + Marker
+pub const TRAILER_LENGTH: usize = 8 + PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH + 8;
+
+/// Where trusted public keys are stored.
+pub static TRUST_DIR: LazyLock<PathBuf> = LazyLock::new(|| AT_HOME.join("keys"));
+
+/// Errors relating to package signatures.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The package has no signature.
+    #[error("Package is unsigned. Pass --insecure to run it anyway")]
+    Unsigned,
+
+    /// The package was signed by a key not in the trust store.
+    #[error("Package is signed by an unknown key ({0}). Pass --insecure to run it anyway")]
+    Unknown(String),
+
+    /// The signature does not match the payload.
+    #[error("Package signature is invalid! The package has been tampered with")]
+    Invalid,
+
+    /// A key could not be parsed.
+    #[error("Invalid key {0}: {1}")]
+    Key(String, String),
+
+    /// Misc IO errors.
+    #[error("I/O Error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A parsed signature trailer.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Trailer {
+    /// The length of the signed content, which immediately precedes the trailer.
+    pub length: u64,
+
+    /// The signer.
+    pub key: [u8; PUBLIC_KEY_LENGTH],
+
+    /// The signature of the content.
+    pub signature: [u8; SIGNATURE_LENGTH],
+}
+impl Trailer {
+    /// Parse a trailer from the end of a buffer, if it has one.
+    #[must_use]
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let start = bytes.len().checked_sub(TRAILER_LENGTH)?;
+        let trailer = &bytes[start..];
+        let (length, rest) = trailer.split_first_chunk::<8>()?;
+        let (key, rest) = rest.split_first_chunk::<PUBLIC_KEY_LENGTH>()?;
+        let (signature, marker) = rest.split_first_chunk::<SIGNATURE_LENGTH>()?;
+        if marker != SIGNATURE_MARKER {
+            return None;
+        }
+
+        Some(Self {
+            length: u64::from_le_bytes(*length),
+            key: *key,
+            signature: *signature,
+        })
+    }
+
+    /// Read the trailer from the end of a file, if it has one.
+    pub fn read(file: &mut File) -> Result<Option<Self>, Error> {
+        let Ok(offset) = i64::try_from(TRAILER_LENGTH) else {
+            return Ok(None);
+        };
+        if file.metadata()?.len() < TRAILER_LENGTH as u64 {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::End(-offset))?;
+        let mut buffer = [0u8; TRAILER_LENGTH];
+        file.read_exact(&mut buffer)?;
+        Ok(Self::parse(&buffer))
+    }
+
+    /// Serialize the trailer.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(TRAILER_LENGTH);
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        bytes.extend_from_slice(&self.key);
+        bytes.extend_from_slice(&self.signature);
+        bytes.extend_from_slice(&SIGNATURE_MARKER);
+        bytes
+    }
+
+    /// A short, human readable identifier for the signer.
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.key)
+    }
+
+    /// Split the signed payload out of a buffer that ends in this trailer.
+    #[must_use]
+    pub fn payload<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        let end = bytes.len().checked_sub(TRAILER_LENGTH)?;
+        let start = end.checked_sub(usize::try_from(self.length).ok()?)?;
+        bytes.get(start..end)
+    }
+}
+
+/// Format the first bytes of a key as hex.
+#[must_use]
+pub fn fingerprint(key: &[u8]) -> String {
+    key.iter().take(8).map(|b| format!("{b:02x}")).collect()
+}
+
+/// Sign a payload with the PEM encoded private key at the path.
+pub fn sign(payload: &[u8], key: &Path) -> Result<Trailer, Error> {
+    let pem = fs::read_to_string(key)?;
+    let key = SigningKey::from_pkcs8_pem(&pem)
+        .map_err(|e| Error::Key(key.display().to_string(), e.to_string()))?;
+
+    Ok(Trailer {
+        length: payload.len() as u64,
+        key: key.verifying_key().to_bytes(),
+        signature: key.sign(payload).to_bytes(),
+    })
+}
+
+/// Get all trusted public keys.
+#[must_use]
+pub fn trusted() -> Vec<VerifyingKey> {
+    fs::read_dir(TRUST_DIR.as_path())
+        .into_iter()
+        .flat_map(|dir| dir.filter_map(Result::ok))
+        .filter_map(|file| {
+            let path = file.path();
+            let pem = fs::read_to_string(&path).ok()?;
+            match VerifyingKey::from_public_key_pem(&pem) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    warn!("Ignoring invalid trusted key {}: {e}", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Verify a payload against its trailer.
+///
+/// An invalid signature is always an error. Unsigned packages, and those signed
+/// by a key outside the trust store, are only permitted if `insecure` is set.
+pub fn verify(payload: &[u8], trailer: Option<&Trailer>, insecure: bool) -> Result<(), Error> {
+    let Some(trailer) = trailer else {
+        if insecure {
+            warn!("Running unsigned package");
+            return Ok(());
+        }
+        return Err(Error::Unsigned);
+    };
+
+    let key = VerifyingKey::from_bytes(&trailer.key)
+        .map_err(|e| Error::Key(trailer.fingerprint(), e.to_string()))?;
+    key.verify_strict(payload, &Signature::from_bytes(&trailer.signature))
+        .map_err(|_| Error::Invalid)?;
+
+    if trusted().contains(&key) {
+        Ok(())
+    } else if insecure {
+        warn!(
+            "Running package signed by unknown key: {}",
+            trailer.fingerprint()
+        );
+        Ok(())
+    } else {
+        Err(Error::Unknown(trailer.fingerprint()))
+    }
+}
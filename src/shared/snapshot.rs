@@ -0,0 +1,361 @@
+//! Snapshots of profile home directories.
+//!
+//! A snapshot is a copy of the home at a point in time, stored in
+//! `$XDG_DATA_HOME/antimony/.snapshots`. Where the filesystem supports it, the copy is
+//! a reflink, which costs nothing until the home diverges from it. Otherwise, the home
+//! is archived into a zstd compressed tarball.
+//!
+//! Snapshots are named by their timestamp, and an optional label: `{timestamp}-{label}`.
+//! Snapshots taken within the same second are numbered: `{timestamp}.{sequence}`.
+
+use crate::shared::{env::DATA_HOME, store::history};
+use anyhow::{Result, anyhow};
+use spawn::{Spawner, StreamMode};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use temp::Temp;
+
+/// Where snapshots are stored.
+pub static SNAPSHOT_DIR: LazyLock<PathBuf> =
+    LazyLock::new(|| DATA_HOME.join("antimony").join(".snapshots"));
+
+/// The label of snapshots taken on launch. Only these are subject to retention.
+pub static AUTO_LABEL: &str = "launch";
+
+/// How many snapshots taken on launch are kept by default.
+pub static RETENTION: u32 = 5;
+
+/// The extension of archived snapshots.
+static ARCHIVE: &str = ".tar.zst";
+
+/// A snapshot of a home.
+pub struct Snapshot {
+    /// Where the snapshot is stored.
+    pub path: PathBuf,
+
+    /// Seconds since the epoch.
+    pub timestamp: u64,
+
+    /// Orders snapshots taken within the same second.
+    pub sequence: u32,
+
+    /// An optional label.
+    pub label: Option<String>,
+
+    /// Whether the snapshot is a tarball, rather than a directory.
+    pub archive: bool,
+}
+impl Snapshot {
+    /// Parse a snapshot from its path.
+    fn parse(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().into_owned();
+        let (name, archive) = match name.strip_suffix(ARCHIVE) {
+            Some(name) => (name.to_owned(), true),
+            None => (name, false),
+        };
+        let (timestamp, label) = match name.split_once('-') {
+            Some((timestamp, label)) => (timestamp, Some(label.to_owned())),
+            None => (name.as_str(), None),
+        };
+        let (timestamp, sequence) = match timestamp.split_once('.') {
+            Some((timestamp, sequence)) => (timestamp, sequence.parse().ok()?),
+            None => (timestamp, 0),
+        };
+
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            sequence,
+            label,
+            archive,
+            path,
+        })
+    }
+
+    /// How long ago the snapshot was taken, in human terms.
+    #[must_use]
+    pub fn age(&self) -> String {
+        history::age(self.timestamp)
+    }
+
+    /// Get a directory with the contents of the snapshot. Archives are extracted
+    /// into a temporary directory, which is removed when dropped.
+    pub fn contents(&self) -> Result<(PathBuf, Option<Temp>)> {
+        if !self.archive {
+            return Ok((self.path.clone(), None));
+        }
+
+        let temp = temp::Builder::new()
+            .owner(user::Mode::Real)
+            .create::<temp::Directory>()?;
+        extract(&self.path, &temp.full())?;
+        Ok((temp.full(), Some(temp)))
+    }
+}
+
+/// Run a command, failing if it does.
+fn run<const N: usize>(command: &str, args: [&str; N]) -> Result<()> {
+    let code = Spawner::new(command)?
+        .args(args)
+        .mode(user::Mode::Real)
+        .error(StreamMode::Discard)
+        .spawn()?
+        .wait()?;
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{command} failed with code {code}"))
+    }
+}
+
+/// Extract an archived snapshot into a directory.
+fn extract(archive: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    run(
+        "tar",
+        [
+            "--zstd",
+            "-xpf",
+            &archive.to_string_lossy(),
+            "-C",
+            &dest.to_string_lossy(),
+        ],
+    )
+}
+
+/// Copy a directory, using reflinks where possible.
+fn copy(src: &Path, dest: &Path) -> Result<()> {
+    run(
+        "cp",
+        [
+            "-a",
+            "--reflink=auto",
+            &src.to_string_lossy(),
+            &dest.to_string_lossy(),
+        ],
+    )
+}
+
+/// List the snapshots in a directory, oldest first.
+#[must_use]
+pub fn list(dir: &Path) -> Vec<Snapshot> {
+    let mut snapshots: Vec<_> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|entry| Snapshot::parse(entry.path()))
+                .collect()
+        })
+        .unwrap_or_default();
+    snapshots.sort_by_key(|snapshot| (snapshot.timestamp, snapshot.sequence));
+    snapshots
+}
+
+/// Find a snapshot by its index, or the most recent with the label.
+pub fn find(dir: &Path, id: &str) -> Result<Snapshot> {
+    let mut snapshots = list(dir);
+    let index = match id.parse::<usize>() {
+        Ok(index) if index < snapshots.len() => Some(index),
+        _ => snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.label.as_deref() == Some(id)),
+    };
+    index
+        .map(|index| snapshots.swap_remove(index))
+        .ok_or_else(|| anyhow!("No such snapshot: {id}"))
+}
+
+/// Take a snapshot of a home.
+pub fn take(home: &Path, dir: &Path, label: Option<&str>) -> Result<Snapshot> {
+    if !home.exists() {
+        return Err(anyhow!("{} does not exist", home.display()));
+    }
+    if let Some(label) = label
+        && (label.is_empty() || label.contains('/'))
+    {
+        return Err(anyhow!("Invalid snapshot label: {label}"));
+    }
+
+    fs::create_dir_all(dir)?;
+    let timestamp = history::now();
+    let (path, archive) = (0..u32::MAX)
+        .map(|sequence| {
+            let stamp = if sequence == 0 {
+                timestamp.to_string()
+            } else {
+                format!("{timestamp}.{sequence}")
+            };
+            let name = match label {
+                Some(label) => format!("{stamp}-{label}"),
+                None => stamp,
+            };
+            (dir.join(&name), dir.join(format!("{name}{ARCHIVE}")))
+        })
+        .find(|(path, archive)| !path.exists() && !archive.exists())
+        .ok_or_else(|| anyhow!("Too many snapshots taken at {timestamp}"))?;
+
+    // A reflink is only worthwhile if it's actually a reflink, otherwise
+    // we'd rather have the compressed archive.
+    let reflink = run(
+        "cp",
+        [
+            "-a",
+            "--reflink=always",
+            &home.to_string_lossy(),
+            &path.to_string_lossy(),
+        ],
+    );
+
+    let path = if let Err(e) = reflink {
+        log::info!("Cannot reflink {}, archiving instead: {e}", home.display());
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        run(
+            "tar",
+            [
+                "--zstd",
+                "-cpf",
+                &archive.to_string_lossy(),
+                "-C",
+                &home.to_string_lossy(),
+                ".",
+            ],
+        )?;
+        archive
+    } else {
+        path
+    };
+
+    Snapshot::parse(path).ok_or_else(|| anyhow!("Failed to create snapshot"))
+}
+
+/// Remove the oldest snapshots taken on launch, keeping `keep`. Zero keeps them all.
+pub fn prune(dir: &Path, keep: usize) -> Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let auto: Vec<_> = list(dir)
+        .into_iter()
+        .filter(|snapshot| snapshot.label.as_deref() == Some(AUTO_LABEL))
+        .collect();
+
+    for snapshot in auto.iter().take(auto.len().saturating_sub(keep)) {
+        log::info!("Removing old snapshot {}", snapshot.path.display());
+        if snapshot.archive {
+            fs::remove_file(&snapshot.path)?;
+        } else {
+            fs::remove_dir_all(&snapshot.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Restore a home to a snapshot. The current home is snapshot beforehand,
+/// so the restore can itself be undone.
+///
+/// The home is locked for the duration, and a home in use by an instance is refused.
+pub fn restore(home: &Path, dir: &Path, snapshot: &Snapshot) -> Result<()> {
+    let _lock = if home.exists() {
+        let file = File::open(home)?;
+        if file.try_lock().is_err() {
+            return Err(anyhow!(
+                "{} is in use. Close the profile before restoring it",
+                home.display()
+            ));
+        }
+        take(home, dir, Some("restore"))?;
+        Some(file)
+    } else {
+        None
+    };
+
+    let name = home
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid home: {}", home.display()))?;
+    let staging = home.with_file_name(format!(".{}.restore", name.to_string_lossy()));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    if snapshot.archive {
+        extract(&snapshot.path, &staging)?;
+    } else {
+        copy(&snapshot.path, &staging)?;
+    }
+
+    if home.exists() {
+        fs::remove_dir_all(home)?;
+    }
+    fs::rename(&staging, home)?;
+    Ok(())
+}
+
+/// How a file has changed since a snapshot.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Change {
+    /// The file is new.
+    Added,
+
+    /// The file no longer exists.
+    Removed,
+
+    /// The file has changed.
+    Modified,
+}
+
+/// Compare a snapshot to the current home, returning the changed paths relative to the home.
+pub fn diff(home: &Path, snapshot: &Snapshot) -> Result<Vec<(Change, String)>> {
+    let (contents, _temp) = snapshot.contents()?;
+    let snap = contents.to_string_lossy().into_owned();
+    let current = home.to_string_lossy().into_owned();
+
+    let output = Spawner::new("diff")?
+        .args(["-rq", "--no-dereference", &snap, &current])
+        .mode(user::Mode::Real)
+        .output(StreamMode::Pipe)
+        .error(StreamMode::Discard)
+        .spawn()?
+        .output_all()?;
+
+    let relative = |path: &str, root: &str| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .trim_start_matches('/')
+            .to_owned()
+    };
+
+    let mut changes: Vec<_> = output
+        .lines()
+        .filter_map(|line| {
+            if let Some(rest) = line.strip_prefix("Only in ") {
+                let (dir, file) = rest.split_once(": ")?;
+                let (change, root) = if dir.starts_with(&snap) {
+                    (Change::Removed, &snap)
+                } else {
+                    (Change::Added, &current)
+                };
+                let dir = relative(dir, root);
+                Some((
+                    change,
+                    if dir.is_empty() {
+                        file.to_owned()
+                    } else {
+                        format!("{dir}/{file}")
+                    },
+                ))
+            } else {
+                let rest = line
+                    .strip_prefix("Files ")
+                    .or_else(|| line.strip_prefix("Symbolic links "))?;
+                let (path, _) = rest.split_once(" and ")?;
+                Some((Change::Modified, relative(path, &snap)))
+            }
+        })
+        .collect();
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(changes)
+}
@@ -0,0 +1,168 @@
+//! The Compression Backend.
+//!
+//! Another wrapping backend, this transparently compresses records with zstd before
+//! they reach the underlying store, and decompresses them on the way back. Only records
+//! above a threshold are compressed, as the frame overhead outweighs the savings on tiny
+//! records. If a dictionary has been trained with `antimony store train`, it is used to
+//! compress the many small bilrost records that would otherwise gain little.
+//!
+//! Records are distinguished by the zstd magic number, so existing uncompressed caches
+//! remain readable. Training does not touch existing records: they stay as they were
+//! stored, and only records stored afterwards use the new dictionary. A record compressed
+//! with a dictionary that has since been retrained can no longer be read, so the read
+//! fails, and callers regenerate it the next time it is needed.
+
+use crate::shared::{
+    Map, Set,
+    env::CACHE_DIR,
+    store::{BackingStore, Object},
+};
+use rayon::prelude::*;
+use std::{
+    any::Any,
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    sync::LazyLock,
+};
+use user::as_effective;
+
+/// The zstd frame magic number.
+static MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// The compression level for records.
+static LEVEL: i32 = 3;
+
+/// Records smaller than this are stored as is.
+pub static THRESHOLD: usize = 256;
+
+/// With a dictionary, much smaller records are worth compressing.
+pub static DICT_THRESHOLD: usize = 64;
+
+/// The maximum size of a trained dictionary.
+pub static DICT_SIZE: usize = 112 * 1024;
+
+/// Where the trained dictionary is stored.
+pub static DICT_PATH: LazyLock<PathBuf> = LazyLock::new(|| CACHE_DIR.join(".dictionary"));
+
+/// The trained dictionary, if one exists.
+static DICTIONARY: LazyLock<Option<Vec<u8>>> = LazyLock::new(|| fs::read(DICT_PATH.as_path()).ok());
+
+/// Compress a record, if it is worth doing so.
+fn compress(content: &[u8]) -> io::Result<Vec<u8>> {
+    // A record that happens to start with the magic is always compressed, so that
+    // anything starting with it can be decompressed unambiguously.
+    let ambiguous = content.starts_with(&MAGIC);
+    match DICTIONARY.as_ref() {
+        Some(dict) if ambiguous || content.len() >= DICT_THRESHOLD => {
+            zstd::bulk::Compressor::with_dictionary(LEVEL, dict)?.compress(content)
+        }
+        None if ambiguous || content.len() >= THRESHOLD => zstd::encode_all(content, LEVEL),
+        _ => Ok(content.to_vec()),
+    }
+}
+
+/// Decompress a record, if it was compressed.
+fn decompress(content: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !content.starts_with(&MAGIC) {
+        return Ok(content);
+    }
+
+    let mut out = Vec::new();
+    let mut decoder = match DICTIONARY.as_ref() {
+        Some(dict) => zstd::Decoder::with_dictionary(content.as_slice(), dict)?,
+        None => zstd::Decoder::with_buffer(content.as_slice())?,
+    };
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Train a dictionary from the records in the store, returning its size.
+pub fn train(store: &dyn BackingStore) -> Result<usize, super::Error> {
+    let samples: Vec<Vec<u8>> = super::OBJECTS
+        .into_iter()
+        .filter(|object| *object != Object::Profile && *object != Object::Feature)
+        .filter_map(|object| store.get(object).ok().map(|names| (object, names)))
+        .flat_map(|(object, names)| {
+            names
+                .into_iter()
+                .filter_map(|name| store.bytes(&name, object).ok())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    if samples.len() < 8 {
+        return Err(io::Error::other("Not enough records to train a dictionary").into());
+    }
+
+    let dict = zstd::dict::from_samples(&samples, DICT_SIZE)?;
+    as_effective!(fs::write(DICT_PATH.as_path(), &dict))??;
+    Ok(dict.len())
+}
+
+/// The Compression Store
+pub struct Store {
+    /// The store we are compressing.
+    backend: Box<dyn BackingStore + Send + Sync>,
+}
+impl Store {
+    /// Construct a new Compression Store around the backend.
+    #[must_use]
+    pub fn new(backend: Box<dyn BackingStore + Send + Sync>) -> Self {
+        Self { backend }
+    }
+}
+impl super::BackingStore for Store {
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn resident(&self) -> bool {
+        self.backend.resident()
+    }
+
+    #[inline]
+    fn fetch(&self, name: &str, object: Object) -> Result<String, super::Error> {
+        Ok(String::from_utf8(self.bytes(name, object)?)?)
+    }
+
+    #[inline]
+    fn bytes(&self, name: &str, object: Object) -> Result<Vec<u8>, super::Error> {
+        Ok(decompress(self.backend.bytes(name, object)?)?)
+    }
+
+    #[inline]
+    fn get(&self, object: Object) -> Result<Set<String>, super::Error> {
+        self.backend.get(object)
+    }
+
+    #[inline]
+    fn exists(&self, name: &str, object: Object) -> bool {
+        self.backend.exists(name, object)
+    }
+
+    #[inline]
+    fn store(&self, name: &str, object: Object, content: &str) -> Result<(), super::Error> {
+        self.dump(name, object, content.as_bytes())
+    }
+
+    #[inline]
+    fn dump(&self, name: &str, object: Object, content: &[u8]) -> Result<(), super::Error> {
+        self.backend.dump(name, object, &compress(content)?)
+    }
+
+    fn bulk(&self, entries: Map<String, Vec<u8>>, object: Object) -> Result<(), super::Error> {
+        let entries = entries
+            .into_par_iter()
+            .map(|(name, content)| Ok((name, compress(&content)?)))
+            .collect::<Result<Map<_, _>, io::Error>>()?;
+        self.backend.bulk(entries, object)
+    }
+
+    #[inline]
+    fn remove(&self, name: &str, object: Object) -> Result<(), super::Error> {
+        self.backend.remove(name, object)
+    }
+}
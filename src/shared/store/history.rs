@@ -0,0 +1,484 @@
+//! The History Backend.
+//!
+//! Like the Memory Backend, this is not a backend in-and-of-itself. It wraps the User Store,
+//! and records a revision each time a Profile or Feature is changed or removed. Each revision
+//! keeps the timestamp, an optional message, and the content itself so that the object can be
+//! rolled back. Diffs are computed from neighbouring revisions when they are displayed.
+//!
+//! Revisions are stored in the wrapped backend as `Object::History`, so they follow the
+//! User Store regardless of which backend it uses.
+
+use crate::shared::{
+    Map, Set,
+    store::{BackingStore, Object},
+};
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, TextDiff};
+use std::{
+    any::Any,
+    str,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+/// The maximum amount of revisions kept for an object. Older revisions are discarded.
+pub static MAX_REVISIONS: usize = 64;
+
+/// A single change to an object.
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct Revision {
+    /// Seconds since the epoch.
+    pub timestamp: u64,
+
+    /// An optional message describing the change.
+    pub message: Option<String>,
+
+    /// The content of the object after the change. None if the object was removed.
+    pub content: Option<String>,
+}
+impl Revision {
+    /// How long ago the revision was made, in human terms.
+    #[must_use]
+    pub fn age(&self) -> String {
+        age(self.timestamp)
+    }
+}
+
+/// Seconds since the epoch.
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// How long ago a timestamp was, in human terms.
+#[must_use]
+pub fn age(timestamp: u64) -> String {
+    let elapsed = now().saturating_sub(timestamp);
+    match elapsed {
+        0..60 => format!("{elapsed} seconds ago"),
+        60..3600 => format!("{} minutes ago", elapsed / 60),
+        3600..86400 => format!("{} hours ago", elapsed / 3600),
+        _ => format!("{} days ago", elapsed / 86400),
+    }
+}
+
+/// The revisions of an object, oldest first.
+#[derive(Deserialize, Serialize, Default)]
+pub struct History {
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
+}
+impl History {
+    /// The unified diff a revision made against the one before it.
+    #[must_use]
+    pub fn diff(&self, index: usize) -> String {
+        let previous = index
+            .checked_sub(1)
+            .and_then(|index| self.revisions.get(index))
+            .and_then(|revision| revision.content.as_deref());
+        let current = self
+            .revisions
+            .get(index)
+            .and_then(|revision| revision.content.as_deref());
+
+        TextDiff::configure()
+            .algorithm(Algorithm::Patience)
+            .diff_lines(previous.unwrap_or(""), current.unwrap_or(""))
+            .unified_diff()
+            .to_string()
+    }
+}
+
+/// Errors reverting an object.
+#[derive(Debug, Error)]
+pub enum RevertError {
+    /// The object has never been changed.
+    #[error("No history")]
+    NoHistory,
+
+    /// The requested revision doesn't exist.
+    #[error("No such revision: {0}")]
+    NoRevision(usize),
+
+    /// The object is already at the requested revision.
+    #[error("Already at revision {0}")]
+    Current(usize),
+
+    /// Errors reading or writing the store.
+    #[error("Store error: {0}")]
+    Store(#[from] super::Error),
+}
+
+/// The name an object's history is stored under.
+#[inline]
+fn key(name: &str, object: Object) -> String {
+    format!("{}:{name}", object.name())
+}
+
+/// Get the history of an object.
+#[must_use]
+pub fn history(store: &dyn BackingStore, name: &str, object: Object) -> History {
+    store
+        .fetch(&key(name, object), Object::History)
+        .ok()
+        .and_then(|str| toml::from_str(&str).ok())
+        .unwrap_or_default()
+}
+
+/// Store an object, attaching a message to the revision if the store records history.
+pub fn store(
+    store: &dyn BackingStore,
+    name: &str,
+    object: Object,
+    content: &str,
+    message: Option<String>,
+) -> Result<(), super::Error> {
+    if let Some(history) = store.as_any().downcast_ref::<Store>() {
+        history.record(name, object, Some(content), message)?;
+        history.backend.store(name, object, content)
+    } else {
+        store.store(name, object, content)
+    }
+}
+
+/// Remove an object, attaching a message to the revision if the store records history.
+pub fn remove(
+    store: &dyn BackingStore,
+    name: &str,
+    object: Object,
+    message: Option<String>,
+) -> Result<(), super::Error> {
+    if let Some(history) = store.as_any().downcast_ref::<Store>() {
+        history.record(name, object, None, message)?;
+        history.backend.remove(name, object)
+    } else {
+        store.remove(name, object)
+    }
+}
+
+/// Roll an object back to a revision, or the one before the latest. Returns the revision
+/// reverted to, or None if the object was removed as it didn't exist before its history.
+pub fn revert(
+    store: &dyn BackingStore,
+    name: &str,
+    object: Object,
+    rev: Option<usize>,
+) -> Result<Option<usize>, RevertError> {
+    let history = history(store, name, object);
+    let latest = history
+        .revisions
+        .len()
+        .checked_sub(1)
+        .ok_or(RevertError::NoHistory)?;
+
+    // Reverting past the first revision means the object didn't exist.
+    let (rev, content) = match rev {
+        Some(rev) => match history.revisions.get(rev) {
+            Some(revision) => (Some(rev), revision.content.clone()),
+            None => return Err(RevertError::NoRevision(rev)),
+        },
+        None => match latest.checked_sub(1) {
+            Some(rev) => (
+                Some(rev),
+                history.revisions.get(rev).and_then(|r| r.content.clone()),
+            ),
+            None => (None, None),
+        },
+    };
+
+    if rev == Some(latest) {
+        return Err(RevertError::Current(latest));
+    }
+
+    let message = Some(rev.map_or_else(
+        || "Reverted to before the first revision".to_owned(),
+        |rev| format!("Reverted to revision {rev}"),
+    ));
+
+    if let Some(content) = content {
+        self::store(store, name, object, &content, message)?;
+        Ok(rev)
+    } else {
+        if store.exists(name, object) {
+            self::remove(store, name, object, message)?;
+        }
+        Ok(None)
+    }
+}
+
+/// The History Store
+pub struct Store {
+    /// The store we are recording.
+    backend: Box<dyn BackingStore + Send + Sync>,
+}
+impl Store {
+    /// Construct a new History Store around the backend.
+    #[must_use]
+    pub fn new(backend: Box<dyn BackingStore + Send + Sync>) -> Self {
+        Self { backend }
+    }
+
+    /// Record a new revision, if the content has changed.
+    fn record(
+        &self,
+        name: &str,
+        object: Object,
+        content: Option<&str>,
+        message: Option<String>,
+    ) -> Result<(), super::Error> {
+        if object != Object::Profile && object != Object::Feature {
+            return Ok(());
+        }
+
+        let previous = self.backend.fetch(name, object).ok();
+        if previous.as_deref() == content {
+            return Ok(());
+        }
+
+        let mut history = history(self.backend.as_ref(), name, object);
+
+        // An object that predates its history keeps what it was as the first revision,
+        // so that the change can still be reverted.
+        if history.revisions.is_empty() && previous.is_some() {
+            history.revisions.push(Revision {
+                timestamp: now(),
+                message: Some("Before the first recorded change".to_owned()),
+                content: previous,
+            });
+        }
+
+        history.revisions.push(Revision {
+            timestamp: now(),
+            message,
+            content: content.map(String::from),
+        });
+
+        if let Some(excess) = history.revisions.len().checked_sub(MAX_REVISIONS) {
+            history.revisions.drain(..excess);
+        }
+
+        match toml::to_string(&history) {
+            Ok(str) => self.backend.store(&key(name, object), Object::History, &str),
+            Err(e) => {
+                log::warn!("Failed to record history for {name}: {e}");
+                Ok(())
+            }
+        }
+    }
+}
+impl super::BackingStore for Store {
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn resident(&self) -> bool {
+        self.backend.resident()
+    }
+
+    #[inline]
+    fn fetch(&self, name: &str, object: Object) -> Result<String, super::Error> {
+        self.backend.fetch(name, object)
+    }
+
+    #[inline]
+    fn bytes(&self, name: &str, object: Object) -> Result<Vec<u8>, super::Error> {
+        self.backend.bytes(name, object)
+    }
+
+    #[inline]
+    fn get(&self, object: Object) -> Result<Set<String>, super::Error> {
+        self.backend.get(object)
+    }
+
+    #[inline]
+    fn exists(&self, name: &str, object: Object) -> bool {
+        self.backend.exists(name, object)
+    }
+
+    #[inline]
+    fn store(&self, name: &str, object: Object, content: &str) -> Result<(), super::Error> {
+        self.record(name, object, Some(content), None)?;
+        self.backend.store(name, object, content)
+    }
+
+    fn dump(&self, name: &str, object: Object, content: &[u8]) -> Result<(), super::Error> {
+        if let Ok(str) = str::from_utf8(content) {
+            self.record(name, object, Some(str), None)?;
+        }
+        self.backend.dump(name, object, content)
+    }
+
+    /// Bulk operations are used for migration, and are not recorded.
+    #[inline]
+    fn bulk(&self, entries: Map<String, Vec<u8>>, object: Object) -> Result<(), super::Error> {
+        self.backend.bulk(entries, object)
+    }
+
+    fn remove(&self, name: &str, object: Object) -> Result<(), super::Error> {
+        self.record(name, object, None, None)?;
+        self.backend.remove(name, object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_REVISIONS, RevertError, Store, history, revert, store};
+    use crate::shared::store::{BackingStore, Object, file};
+
+    /// A history store over a temporary file store.
+    fn temp_store() -> (temp::Temp, Store) {
+        let dir = temp::Builder::new()
+            .create::<temp::Directory>()
+            .expect("Failed to create temporary directory");
+        let backend = file::Store::new(&dir.full().to_string_lossy(), "toml");
+        (dir, Store::new(Box::new(backend)))
+    }
+
+    #[test]
+    fn records_revisions() {
+        let (_dir, history_store) = temp_store();
+        let object = Object::Profile;
+
+        store(
+            &history_store,
+            "test",
+            object,
+            "a = 1\n",
+            Some("First".to_owned()),
+        )
+        .expect("Failed to store");
+        history_store
+            .store("test", object, "a = 2\n")
+            .expect("Failed to store");
+
+        // Unchanged content isn't a revision.
+        history_store
+            .store("test", object, "a = 2\n")
+            .expect("Failed to store");
+
+        let history = history(&history_store, "test", object);
+        assert_eq!(history.revisions.len(), 2);
+        assert_eq!(
+            history.revisions.first().and_then(|r| r.message.as_deref()),
+            Some("First")
+        );
+        assert!(history.diff(1).contains("-a = 1"));
+        assert!(history.diff(1).contains("+a = 2"));
+    }
+
+    #[test]
+    fn reverts() {
+        let (_dir, history_store) = temp_store();
+        let object = Object::Feature;
+        for content in ["a = 1\n", "a = 2\n", "a = 3\n"] {
+            history_store
+                .store("test", object, content)
+                .expect("Failed to store");
+        }
+
+        assert!(matches!(
+            revert(&history_store, "test", object, Some(2)),
+            Err(RevertError::Current(2))
+        ));
+        assert!(matches!(
+            revert(&history_store, "test", object, Some(10)),
+            Err(RevertError::NoRevision(10))
+        ));
+
+        // Reverting is itself a revision.
+        assert_eq!(
+            revert(&history_store, "test", object, Some(0)).expect("Failed to revert"),
+            Some(0)
+        );
+        assert_eq!(
+            history_store
+                .fetch("test", object)
+                .expect("Failed to fetch"),
+            "a = 1\n"
+        );
+        assert_eq!(history(&history_store, "test", object).revisions.len(), 4);
+
+        // Without a revision, the latest change is undone.
+        assert_eq!(
+            revert(&history_store, "test", object, None).expect("Failed to revert"),
+            Some(2)
+        );
+        assert_eq!(
+            history_store
+                .fetch("test", object)
+                .expect("Failed to fetch"),
+            "a = 3\n"
+        );
+    }
+
+    #[test]
+    fn reverts_before_first_revision() {
+        let (_dir, history_store) = temp_store();
+        let object = Object::Profile;
+        history_store
+            .store("test", object, "a = 1\n")
+            .expect("Failed to store");
+
+        assert_eq!(
+            revert(&history_store, "test", object, None).expect("Failed to revert"),
+            None
+        );
+        assert!(!history_store.exists("test", object));
+        assert!(matches!(
+            revert(&history_store, "missing", object, None),
+            Err(RevertError::NoHistory)
+        ));
+    }
+
+    #[test]
+    fn reverts_before_history() {
+        let (_dir, history_store) = temp_store();
+        let object = Object::Profile;
+
+        // Stored directly, as if the profile existed before history was enabled.
+        history_store
+            .backend
+            .store("test", object, "a = 1\n")
+            .expect("Failed to store");
+        store(&history_store, "test", object, "a = 2\n", None).expect("Failed to store");
+        assert_eq!(history(&history_store, "test", object).revisions.len(), 2);
+
+        assert_eq!(
+            revert(&history_store, "test", object, None).expect("Failed to revert"),
+            Some(0)
+        );
+        assert_eq!(
+            history_store
+                .fetch("test", object)
+                .expect("Failed to fetch"),
+            "a = 1\n"
+        );
+    }
+
+    #[test]
+    fn caps_revisions() {
+        let (_dir, history_store) = temp_store();
+        let object = Object::Profile;
+        let total = MAX_REVISIONS.saturating_add(5);
+        for index in 0..total {
+            history_store
+                .store("test", object, &format!("a = {index}\n"))
+                .expect("Failed to store");
+        }
+
+        let history = history(&history_store, "test", object);
+        assert_eq!(history.revisions.len(), MAX_REVISIONS);
+        assert_eq!(
+            history.revisions.last().and_then(|r| r.content.as_deref()),
+            Some(format!("a = {}\n", total.saturating_sub(1)).as_str())
+        );
+        assert_eq!(
+            history.revisions.first().and_then(|r| r.content.as_deref()),
+            Some("a = 5\n")
+        );
+    }
+}
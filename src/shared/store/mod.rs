@@ -7,16 +7,21 @@
 //! By defining a common interface, they can be swapped out relatively easily,
 //! and migrating from one to the other.
 
+pub mod compress;
 pub mod file;
+pub mod history;
 pub mod mem;
+pub mod sqlite;
 
 use crate::shared::{
     Map, Set,
     config::CONFIG_FILE,
     env::{AT_CONFIG, CACHE_DIR, USER_NAME},
 };
+use clap::ValueEnum;
+use log::{error, warn};
 use parking_lot::Mutex;
-use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::{
     any::Any,
     error,
@@ -36,30 +41,61 @@ pub struct Store {
 }
 impl Store {
     pub fn init(t: StoreType) -> Self {
-        let backing: Box<dyn BackingStore + Send + Sync> = match t {
-            StoreType::System => Box::new(file::Store::new(
-                &format!("{}", AT_CONFIG.display()),
-                "toml",
-            )),
-
-            StoreType::User => Box::new(file::Store::new(
-                &format!("{}/{}", AT_CONFIG.display(), USER_NAME.as_str()),
-                "toml",
-            )),
-            StoreType::Cache => {
-                let cache: Box<dyn BackingStore + Send + Sync> = Box::new(file::Store::new(
-                    &format!("{}", CACHE_DIR.display()),
-                    "cache",
-                ));
-
-                let value = *CACHE.lock();
-                if let Some(read) = value {
-                    let name = format!("{t}_cache");
-                    Box::new(mem::Store::new(&name, cache, read))
-                } else {
-                    cache
-                }
-            }
+        Self::with(t, CONFIG_FILE.store()).unwrap_or_else(|e| {
+            error!("Failed to open {t} database, falling back to files: {e}");
+            Self::wrap(
+                t,
+                Box::new(file::Store::new(&Self::path(t), Self::extension(t))),
+            )
+        })
+    }
+
+    /// The directory of a store.
+    fn path(t: StoreType) -> String {
+        match t {
+            StoreType::System => format!("{}", AT_CONFIG.display()),
+            StoreType::User => format!("{}/{}", AT_CONFIG.display(), USER_NAME.as_str()),
+            StoreType::Cache => format!("{}", CACHE_DIR.display()),
+        }
+    }
+
+    /// The extension of files in a store.
+    fn extension(t: StoreType) -> &'static str {
+        if t == StoreType::Cache {
+            "cache"
+        } else {
+            "toml"
+        }
+    }
+
+    /// Initialize a store with an explicit backend, rather than the configured one.
+    pub fn with(t: StoreType, backend: Backend) -> Result<Self, Error> {
+        let path = Self::path(t);
+        let disk: Box<dyn BackingStore + Send + Sync> = match backend {
+            Backend::File => Box::new(file::Store::new(&path, Self::extension(t))),
+            Backend::Sqlite => Box::new(sqlite::Store::new(&path)?),
+        };
+        Ok(Self::wrap(t, disk))
+    }
+
+    /// Layer compression, memory, and history over the on-disk store, as the type requires.
+    fn wrap(t: StoreType, disk: Box<dyn BackingStore + Send + Sync>) -> Self {
+        let disk: Box<dyn BackingStore + Send + Sync> = if t == StoreType::Cache {
+            Box::new(compress::Store::new(disk))
+        } else {
+            disk
+        };
+
+        let value = *CACHE.lock();
+        let backing: Box<dyn BackingStore + Send + Sync> = if t == StoreType::Cache
+            && let Some(read) = value
+        {
+            let name = format!("{t}_cache");
+            Box::new(mem::Store::new(&name, disk, read))
+        } else if t == StoreType::User {
+            Box::new(history::Store::new(disk))
+        } else {
+            disk
         };
         Self { backing }
     }
@@ -91,6 +127,26 @@ impl Display for StoreType {
     }
 }
 
+/// The backends that can be selected for on-disk storage.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Copy, Clone, Default, ValueEnum)]
+#[serde(deny_unknown_fields)]
+pub enum Backend {
+    /// Each Object is stored as its own file.
+    #[default]
+    File,
+
+    /// Objects are stored in a single SQLite database.
+    Sqlite,
+}
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "File"),
+            Self::Sqlite => write!(f, "Sqlite"),
+        }
+    }
+}
+
 /// Store errors
 #[derive(Debug, Error)]
 pub enum Error {
@@ -114,7 +170,7 @@ pub enum Error {
 }
 
 /// Each Object, for iteration.
-pub static OBJECTS: [Object; 7] = [
+pub static OBJECTS: [Object; 8] = [
     Object::Profile,
     Object::Feature,
     Object::Directories,
@@ -122,6 +178,7 @@ pub static OBJECTS: [Object; 7] = [
     Object::Libraries,
     Object::Binaries,
     Object::Search,
+    Object::History,
 ];
 
 /// The kinds of things a backend can store.
@@ -134,6 +191,7 @@ pub enum Object {
     Libraries,
     Binaries,
     Search,
+    History,
 }
 impl Object {
     const fn name(self) -> &'static str {
@@ -145,6 +203,7 @@ impl Object {
             Self::Libraries => "libraries",
             Self::Binaries => "binaries",
             Self::Search => "search",
+            Self::History => "history",
         }
     }
 }
@@ -234,11 +293,31 @@ pub fn export(store: &dyn BackingStore) -> Map<Object, Set<String>> {
     map
 }
 
+/// Copy every object from one store into another, returning how many were copied.
+pub fn migrate(src: &dyn BackingStore, dest: &dyn BackingStore) -> Result<usize, Error> {
+    let mut count = 0usize;
+    for (object, names) in export(src) {
+        let entries: Map<String, Vec<u8>> = names
+            .into_iter()
+            .filter_map(|name| match src.bytes(&name, object) {
+                Ok(bytes) => Some((name, bytes)),
+                Err(e) => {
+                    warn!("Failed to read {object} {name}, skipping: {e}");
+                    None
+                }
+            })
+            .collect();
+        count = count.saturating_add(entries.len());
+        dest.bulk(entries, object)?;
+    }
+    Ok(count)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::shared::{
         env::{AT_CONFIG, AT_HOME, PWD},
-        store::{BackingStore, Object, file},
+        store::{BackingStore, Object, file, sqlite},
     };
 
     #[allow(clippy::needless_pass_by_value)]
@@ -274,4 +353,14 @@ mod tests {
             backend_test(Box::new(store));
         }
     }
+
+    #[test]
+    fn sqlite_backend() {
+        let dir = temp::Builder::new()
+            .create::<temp::Directory>()
+            .expect("Failed to create temporary directory");
+        let store =
+            sqlite::Store::new(&dir.full().to_string_lossy()).expect("Failed to open database");
+        backend_test(Box::new(store));
+    }
 }
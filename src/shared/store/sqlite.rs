@@ -0,0 +1,146 @@
+//! The SQLite Backend.
+//!
+//! Rather than storing each Object as a discrete file, the SQLite Backend keeps
+//! the entire store within a single, indexed database. The Cache Store in particular
+//! can grow to thousands of small records, and collapsing them into a single file
+//! avoids the cost of opening each one on slow disks.
+//!
+//! This Backend is best for slow disks, or caches with many records.
+
+use crate::shared::{Map, Set, store::Object};
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::{any::Any, fs, path::Path};
+use user::as_effective;
+
+/// The SQLite Store
+pub struct Store {
+    /// The connection to the database
+    conn: Mutex<Connection>,
+}
+impl Store {
+    /// Construct a new SQLite Store, creating the database if needed.
+    ///
+    /// ## Errors
+    /// If the database could not be created or opened.
+    pub fn new(path: &str) -> Result<Self, super::Error> {
+        let conn = as_effective!(Result<Connection, super::Error>, {
+            let db = Path::new(path).join("store.db");
+            if let Some(parent) = db.parent()
+                && !parent.exists()
+            {
+                fs::create_dir_all(parent)?;
+            }
+
+            let conn = Connection::open(db)?;
+            conn.execute_batch(
+                r"
+                CREATE TABLE IF NOT EXISTS objects (
+                    kind TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    content BLOB NOT NULL,
+                    PRIMARY KEY (kind, name)
+                ) WITHOUT ROWID;
+                ",
+            )?;
+
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+            conn.pragma_update(None, "temp_store", "MEMORY")?;
+            conn.pragma_update(None, "busy_timeout", "1000")?;
+            Ok(conn)
+        })??;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+impl super::BackingStore for Store {
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn resident(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn fetch(&self, name: &str, object: Object) -> Result<String, super::Error> {
+        Ok(String::from_utf8(self.bytes(name, object)?)?)
+    }
+
+    fn bytes(&self, name: &str, object: Object) -> Result<Vec<u8>, super::Error> {
+        Ok(self.conn.lock().query_row(
+            "SELECT content FROM objects WHERE kind = ?1 AND name = ?2",
+            params![object.name(), name],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn get(&self, object: Object) -> Result<Set<String>, super::Error> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT name FROM objects WHERE kind = ?1")?;
+        let names = stmt
+            .query_map([object.name()], |row| row.get::<_, String>(0))?
+            .flatten()
+            .collect();
+        Ok(names)
+    }
+
+    fn exists(&self, name: &str, object: Object) -> bool {
+        self.conn
+            .lock()
+            .query_row(
+                "SELECT 1 FROM objects WHERE kind = ?1 AND name = ?2",
+                params![object.name(), name],
+                |_| Ok(()),
+            )
+            .optional()
+            .is_ok_and(|row| row.is_some())
+    }
+
+    #[inline]
+    fn store(&self, name: &str, object: Object, content: &str) -> Result<(), super::Error> {
+        self.dump(name, object, content.as_bytes())
+    }
+
+    fn dump(&self, name: &str, object: Object, content: &[u8]) -> Result<(), super::Error> {
+        as_effective!(Result<(), super::Error>, {
+            self.conn.lock().execute(
+                "INSERT OR REPLACE INTO objects (kind, name, content) VALUES (?1, ?2, ?3)",
+                params![object.name(), name, content],
+            )?;
+            Ok(())
+        })?
+    }
+
+    fn bulk(&self, entries: Map<String, Vec<u8>>, object: Object) -> Result<(), super::Error> {
+        as_effective!(Result<(), super::Error>, {
+            let mut conn = self.conn.lock();
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT OR REPLACE INTO objects (kind, name, content) VALUES (?1, ?2, ?3)",
+                )?;
+                for (name, content) in entries {
+                    stmt.execute(params![object.name(), name, content])?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })?
+    }
+
+    fn remove(&self, name: &str, object: Object) -> Result<(), super::Error> {
+        as_effective!(Result<(), super::Error>, {
+            self.conn.lock().execute(
+                "DELETE FROM objects WHERE kind = ?1 AND name = ?2",
+                params![object.name(), name],
+            )?;
+            Ok(())
+        })?
+    }
+}
@@ -61,6 +61,41 @@ pub enum Error {
     Errno(#[from] errno::Errno),
 }
 
+/// The schema of the database.
+static SCHEMA: &str = r"
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS binaries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS syscalls (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name INTEGER NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS binary_syscalls (
+        binary_id INTEGER NOT NULL,
+        syscall_id INTEGER NOT NULL,
+        PRIMARY KEY (binary_id, syscall_id),
+        FOREIGN KEY (binary_id) REFERENCES binaries(id) ON DELETE CASCADE,
+        FOREIGN KEY (syscall_id) REFERENCES syscalls(id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS profiles (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT UNIQUE NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS profile_binaries (
+        profile_id INTEGER NOT NULL,
+        binary_id INTEGER NOT NULL,
+        PRIMARY KEY (profile_id, binary_id),
+        FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
+        FOREIGN KEY (binary_id) REFERENCES binaries(id) ON DELETE CASCADE
+    );
+";
+
 /// Get a new connection to the database.
 fn new_connection() -> Result<Connection, Error> {
     as_effective!({
@@ -75,41 +110,7 @@ fn new_connection() -> Result<Connection, Error> {
             Connection::open(db)?
         } else {
             let conn = Connection::open(db)?;
-            conn.execute_batch(
-                r"
-                PRAGMA foreign_keys = ON;
-                CREATE TABLE IF NOT EXISTS binaries (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    path TEXT NOT NULL UNIQUE
-                );
-
-                CREATE TABLE IF NOT EXISTS syscalls (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    name INTEGER NOT NULL UNIQUE
-                );
-
-                CREATE TABLE IF NOT EXISTS binary_syscalls (
-                    binary_id INTEGER NOT NULL,
-                    syscall_id INTEGER NOT NULL,
-                    PRIMARY KEY (binary_id, syscall_id),
-                    FOREIGN KEY (binary_id) REFERENCES binaries(id) ON DELETE CASCADE,
-                    FOREIGN KEY (syscall_id) REFERENCES syscalls(id) ON DELETE CASCADE
-                );
-
-                CREATE TABLE IF NOT EXISTS profiles (
-                        id INTEGER PRIMARY KEY AUTOINCREMENT,
-                        name TEXT UNIQUE NOT NULL
-                    );
-
-                CREATE TABLE IF NOT EXISTS profile_binaries (
-                    profile_id INTEGER NOT NULL,
-                    binary_id INTEGER NOT NULL,
-                    PRIMARY KEY (profile_id, binary_id),
-                    FOREIGN KEY (profile_id) REFERENCES profiles(id) ON DELETE CASCADE,
-                    FOREIGN KEY (binary_id) REFERENCES binaries(id) ON DELETE CASCADE
-                );
-                ",
-            )?;
+            conn.execute_batch(SCHEMA)?;
             conn
         };
 
@@ -389,6 +390,69 @@ pub fn get_calls(name: &str, p_binaries: &Set<String>) -> PolicyPair {
     (syscalls, bwrap)
 }
 
+/// Syscalls that every filter must allow.
+static REQUIRED: [&str; 3] = ["execve", "wait4", "exit"];
+
+/// Get the path of the compiled BPF for a set of syscalls within the directory.
+fn bpf_path(syscalls: &[i32], dir: &Path) -> Result<PathBuf, Error> {
+    let mut s = DefaultHasher::new();
+    syscalls.hash(&mut s);
+    let hash = format!("{}", s.finish());
+
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(dir.join(format!("{hash}.bpf")))
+}
+
+/// Return an Enforcing Policy from a precomputed allowlist of syscall names, such as the
+/// one embedded within a package. This needs neither the database nor the monitor.
+/// The compiled BPF is cached within `cache`.
+pub fn from_allowlist(
+    syscalls: &[String],
+    bwrap: &[String],
+    cache: &Path,
+) -> Result<(Filter, OwnedFd), Error> {
+    let resolve = |names: &[String]| -> Set<i32> {
+        names
+            .iter()
+            .filter_map(|name| {
+                let num = get_num(name);
+                if num.is_none() {
+                    warn!("Ignoring unknown syscall in allowlist: {name}");
+                }
+                num
+            })
+            .collect()
+    };
+
+    let mut syscalls = resolve(syscalls);
+    for required in REQUIRED {
+        syscalls.insert(Syscall::from_name(required)?.get_number());
+    }
+    let bwrap: Set<i32> = resolve(bwrap).difference(&syscalls).copied().collect();
+
+    let mut filter = Filter::new(Action::KillProcess)?;
+    filter.set_attribute(Attribute::ThreadSync(true))?;
+
+    let syscalls = syscalls.into_iter().collect::<Vec<_>>();
+    for syscall in &syscalls {
+        filter.add_rule(Action::Allow, Syscall::from_number(*syscall))?;
+    }
+
+    let bpf = bpf_path(&syscalls, cache)?;
+    let fd = if bpf.exists() {
+        File::open(&bpf)?.into()
+    } else {
+        filter.write(&bpf)?
+    };
+
+    for syscall in bwrap {
+        filter.add_rule(Action::Allow, Syscall::from_number(syscall))?;
+    }
+    Ok((filter, fd))
+}
+
 /// Return a new Policy
 #[allow(
     clippy::unwrap_used,
@@ -423,7 +487,7 @@ pub fn new(
         filter.set_attribute(Attribute::NoNewPrivileges(false))?;
     }
 
-    for required in ["execve", "wait4", "exit"] {
+    for required in REQUIRED {
         syscalls.insert(Syscall::from_name(required)?.get_number());
     }
 
@@ -435,21 +499,7 @@ pub fn new(
     }
 
     let fd = if policy == SeccompPolicy::Enforcing {
-        let mut s = DefaultHasher::new();
-        syscalls.hash(&mut s);
-        let hash = format!("{}", s.finish());
-
-        let bpf = AT_HOME
-            .join("cache")
-            .join(".seccomp")
-            .join(format!("{hash}.bpf"));
-
-        if let Some(parent) = bpf.parent()
-            && !parent.exists()
-        {
-            fs::create_dir_all(parent)?;
-        }
-
+        let bpf = bpf_path(&syscalls, &AT_HOME.join("cache").join(".seccomp"))?;
         Some(if bpf.exists() {
             File::open(&bpf)?.into()
         } else {
@@ -554,8 +604,8 @@ pub fn merge_database(db: &Path) -> anyhow::Result<()> {
         CONNECTION.with_borrow_mut(|conn| {
             let tx = conn.transaction()?;
             tx.execute(
-                &format!("ATTACH DATABASE '{}' AS other", temp.full().display()),
-                [],
+                "ATTACH DATABASE ?1 AS other",
+                params![temp.full().to_string_lossy()],
             )?;
             tx.execute_batch(
                 "
@@ -590,3 +640,65 @@ pub fn merge_database(db: &Path) -> anyhow::Result<()> {
         })
     })?
 }
+
+/// Export the rows of a single profile into a standalone database, returning its contents.
+/// The result can be merged into another database with `merge_database`.
+pub fn export_profile(name: &str) -> anyhow::Result<Vec<u8>> {
+    as_effective!({
+        let temp = temp::Builder::new()
+            .within(AT_HOME.join("seccomp"))
+            .owner(Mode::Effective)
+            .create::<temp::File>()?;
+        Connection::open(temp.full())?.execute_batch(SCHEMA)?;
+
+        CONNECTION.with_borrow_mut(|conn| -> anyhow::Result<()> {
+            conn.execute(
+                "ATTACH DATABASE ?1 AS export",
+                params![temp.full().to_string_lossy()],
+            )?;
+
+            let result = || -> anyhow::Result<()> {
+                let tx = conn.transaction()?;
+                tx.execute("INSERT INTO export.profiles (name) VALUES (?1)", [name])?;
+                tx.execute(
+                    "
+        INSERT OR IGNORE INTO export.binaries (path)
+        SELECT b.path
+        FROM binaries b
+        JOIN profile_binaries pb ON b.id = pb.binary_id
+        JOIN profiles p ON p.id = pb.profile_id
+        WHERE p.name = ?1",
+                    [name],
+                )?;
+                tx.execute_batch(
+                    "
+        INSERT OR IGNORE INTO export.syscalls (name)
+        SELECT DISTINCT s.name
+        FROM syscalls s
+        JOIN binary_syscalls bs ON s.id = bs.syscall_id
+        JOIN binaries b ON b.id = bs.binary_id
+        JOIN export.binaries eb ON eb.path = b.path;
+
+        INSERT OR IGNORE INTO export.binary_syscalls (binary_id, syscall_id)
+        SELECT eb.id, es.id
+        FROM binary_syscalls bs
+        JOIN binaries b ON b.id = bs.binary_id
+        JOIN export.binaries eb ON eb.path = b.path
+        JOIN syscalls s ON s.id = bs.syscall_id
+        JOIN export.syscalls es ON es.name = s.name;
+
+        INSERT OR IGNORE INTO export.profile_binaries (profile_id, binary_id)
+        SELECT ep.id, eb.id
+        FROM export.profiles ep, export.binaries eb;
+        ",
+                )?;
+                tx.commit()?;
+                Ok(())
+            }();
+
+            conn.execute("DETACH DATABASE export", [])?;
+            result
+        })?;
+        Ok(fs::read(temp.full())?)
+    })?
+}
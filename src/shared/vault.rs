@@ -0,0 +1,225 @@
+//! Encrypted homes, backed by gocryptfs.
+//!
+//! The cipher directory sits where the home usually would, and is mounted in
+//! `$XDG_RUNTIME_DIR/antimony/.vault` while the profile is running. The vault is only
+//! mounted under an exclusive lock, after which every instance holds a shared lock on
+//! it, and the last one to close unmounts it, so concurrent instances share a single
+//! mount.
+
+use crate::shared::{env::RUNTIME_DIR, profile::home::HomeSecret};
+use anyhow::{Result, anyhow};
+use dbus::{arg::PropMap, blocking::LocalConnection};
+use dialoguer::console::Term;
+use log::warn;
+use nix::unistd::pipe;
+use sha2::{Digest, Sha256};
+use spawn::{Spawner, StreamMode};
+use std::{
+    fs::{self, File},
+    io::{IsTerminal, Read, Write, stdin},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+/// Where vaults are mounted.
+pub static VAULT_DIR: LazyLock<PathBuf> =
+    LazyLock::new(|| RUNTIME_DIR.join("antimony").join(".vault"));
+
+/// The configuration file gocryptfs creates in an initialized cipher directory.
+static CONFIG: &str = "gocryptfs.conf";
+
+/// A mounted vault.
+pub struct Vault {
+    /// Where the plain-text home is mounted.
+    pub mount: PathBuf,
+
+    /// The shared lock held by this instance, until it is closed.
+    lock: Option<File>,
+}
+impl Vault {
+    /// Open the vault at `cipher`, mounting it if no other instance has.
+    /// The vault is initialized if it doesn't exist.
+    pub fn open(cipher: &Path, name: &str, secret: HomeSecret) -> Result<Self> {
+        let mount = VAULT_DIR.join(name);
+        fs::create_dir_all(&mount)?;
+
+        // Block while the last instance of another launch is unmounting.
+        let lock = File::create(VAULT_DIR.join(format!("{name}.lock")))?;
+        loop {
+            lock.lock_shared()?;
+            if mounted(&mount) {
+                break;
+            }
+
+            // Only one launch may mount the vault. Converting the lock isn't atomic,
+            // so check again once we have it, and once it's been downgraded.
+            lock.lock()?;
+            if mounted(&mount) {
+                continue;
+            }
+
+            let init = !cipher.join(CONFIG).exists();
+            if init
+                && cipher
+                    .read_dir()
+                    .is_ok_and(|mut entries| entries.next().is_some())
+            {
+                return Err(anyhow!(
+                    "{} is not an encrypted home, but is not empty. Move its contents out, and back into the home once it has been encrypted.",
+                    cipher.display()
+                ));
+            }
+
+            let passphrase = passphrase(name, secret, init)?;
+            if init {
+                fs::create_dir_all(cipher)?;
+                gocryptfs(["-init", "-q", &cipher.to_string_lossy()], &passphrase)?;
+            }
+            gocryptfs(
+                ["-q", &cipher.to_string_lossy(), &mount.to_string_lossy()],
+                &passphrase,
+            )?;
+        }
+        Ok(Self {
+            mount,
+            lock: Some(lock),
+        })
+    }
+
+    /// Close the vault, unmounting it if this is the last instance using it.
+    pub fn close(mut self) -> Result<()> {
+        self.release()
+    }
+
+    /// Release the lock, unmounting the vault if nobody else holds it.
+    fn release(&mut self) -> Result<()> {
+        let Some(lock) = self.lock.take() else {
+            return Ok(());
+        };
+
+        // Converting to an exclusive lock only succeeds if nobody else holds it.
+        if lock.try_lock().is_ok() && mounted(&self.mount) {
+            let code = Spawner::new("fusermount3")?
+                .args(["-u", &self.mount.to_string_lossy()])
+                .mode(user::Mode::Real)
+                .new_privileges(true)
+                .spawn()?
+                .wait()?;
+            if code != 0 {
+                return Err(anyhow!("Failed to unmount {}", self.mount.display()));
+            }
+        }
+        Ok(())
+    }
+}
+impl Drop for Vault {
+    // A vault that was never closed, such as when setup fails after it was opened,
+    // must still be unmounted.
+    fn drop(&mut self) {
+        if let Err(e) = self.release() {
+            warn!("Failed to close encrypted home: {e}");
+        }
+    }
+}
+
+/// Whether something is mounted at path.
+fn mounted(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    fs::read_to_string("/proc/self/mountinfo").is_ok_and(|info| {
+        info.lines()
+            .any(|line| line.split(' ').nth(4) == Some(path.as_ref()))
+    })
+}
+
+/// Run gocryptfs, feeding it the passphrase over stdin.
+fn gocryptfs<const N: usize>(args: [&str; N], passphrase: &str) -> Result<()> {
+    let (read, write) = pipe()?;
+    File::from(write).write_all(format!("{passphrase}\n").as_bytes())?;
+
+    let code = Spawner::new("gocryptfs")?
+        .args(args)
+        .input(StreamMode::Fd(read))
+        .mode(user::Mode::Real)
+        .new_privileges(true)
+        .spawn()?
+        .wait()?;
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("gocryptfs failed with code {code}"))
+    }
+}
+
+/// Get the passphrase of the vault.
+fn passphrase(name: &str, secret: HomeSecret, init: bool) -> Result<String> {
+    match secret {
+        HomeSecret::Portal => portal(name),
+        HomeSecret::Prompt => prompt(name, init),
+    }
+}
+
+/// Derive the passphrase from the application secret of the Secret Portal,
+/// such that each home has its own.
+fn portal(name: &str) -> Result<String> {
+    let connection = LocalConnection::new_session()?;
+    let proxy = connection.with_proxy(
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        Duration::from_secs(30),
+    );
+
+    // The portal writes the secret into the pipe, and closes it once done.
+    let (read, write) = pipe()?;
+    let _: (dbus::Path,) = proxy.method_call(
+        "org.freedesktop.portal.Secret",
+        "RetrieveSecret",
+        (write, PropMap::new()),
+    )?;
+
+    let mut secret = Vec::new();
+    File::from(read).read_to_end(&mut secret)?;
+    if secret.is_empty() {
+        return Err(anyhow!("The Secret Portal did not provide a secret"));
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&secret);
+    hasher.update(name.as_bytes());
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Ask the user for the passphrase, on the terminal if there is one.
+fn prompt(name: &str, init: bool) -> Result<String> {
+    let passphrase = if stdin().is_terminal() {
+        let term = Term::stderr();
+        term.write_str(&format!("Passphrase for {name}: "))?;
+        let passphrase = term.read_secure_line()?;
+        if init {
+            term.write_str("Confirm passphrase: ")?;
+            if term.read_secure_line()? != passphrase {
+                return Err(anyhow!("Passphrases do not match"));
+            }
+        }
+        passphrase
+    } else {
+        let title = format!("Enter the passphrase for {name}");
+        let output = Spawner::new("kdialog")?
+            .args([if init { "--newpassword" } else { "--password" }, &title])
+            .mode(user::Mode::Real)
+            .output(StreamMode::Pipe)
+            .spawn()?
+            .output_all()?;
+        output.trim_end_matches('\n').to_owned()
+    };
+
+    if passphrase.is_empty() {
+        Err(anyhow!("No passphrase provided for {name}"))
+    } else {
+        Ok(passphrase)
+    }
+}